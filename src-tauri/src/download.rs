@@ -1,17 +1,99 @@
 use futures_util::StreamExt;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use tauri::{AppHandle, Emitter, Manager, State};
 use tokio::io::{AsyncSeekExt, AsyncWriteExt};
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{mpsc, oneshot, Mutex};
+
+mod client;
+mod extract;
+mod filename;
+#[cfg(not(target_os = "android"))]
+mod launcher;
+mod media;
+mod mime;
+mod pipeline;
+mod progress;
+mod reconcile;
+mod settings;
+mod share;
+mod store;
+mod throttle;
+
+use client::HttpClient;
+use filename::{FilenameReservation, Reservation, ReservedNames};
+use progress::ProgressTracker;
+use store::OpenError;
+use throttle::{SpeedLimiter, TokenBucket};
+
+pub use filename::ConflictPolicy;
+pub use progress::TransferState;
+pub use reconcile::ReconcileReport;
+pub use settings::DownloadSettings;
+pub use store::DownloadStore;
 
 #[derive(Clone, serde::Serialize)]
 pub struct DownloadProgress {
     pub id: u64,
     pub received: u64,
     pub total: u64,
+    // 平滑后的速度（字节/秒）
+    pub speed: u64,
+    // 预计剩余时间和已用时间（毫秒），均不含暂停时间
+    pub eta: Option<u64>,
+    pub elapsed: u64,
+    pub state: TransferState,
+}
+
+// 进行中任务的完整状态，webview 重新加载后据此恢复界面
+#[derive(Debug, Clone, Serialize)]
+pub struct DownloadSnapshot {
+    pub id: u64,
+    pub record_id: String,
+    pub url: String,
+    pub filename: String,
+    pub path: String,
+    pub received: u64,
+    pub total: u64,
+    pub speed: u64,
+    pub eta: Option<u64>,
+    pub state: TransferState,
+    pub error: Option<String>,
+}
+
+type SharedSnapshot = Arc<std::sync::Mutex<DownloadSnapshot>>;
+
+// 更新任务快照，状态发生变化时发出统一的 download-state-changed 事件
+fn update_snapshot(
+    app: &AppHandle,
+    snapshot: &SharedSnapshot,
+    update: impl FnOnce(&mut DownloadSnapshot),
+) {
+    let (changed, current) = {
+        let Ok(mut snapshot) = snapshot.lock() else {
+            return;
+        };
+        let previous = snapshot.state;
+        update(&mut snapshot);
+        (snapshot.state != previous, snapshot.clone())
+    };
+
+    if changed {
+        app.emit("download-state-changed", current).unwrap_or(());
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DownloadStatus {
+    #[default]
+    Completed,
+    Failed,
+    Cancelled,
+    Paused,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,184 +103,1316 @@ pub struct DownloadRecord {
     pub path: String,
     pub size: u64,
     pub timestamp: i64,
+    #[serde(default)]
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub missing: bool,
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub server: Option<String>,
+    #[serde(default)]
+    pub sender_name: Option<String>,
+    #[serde(default)]
+    pub sender_id: Option<String>,
+    #[serde(default)]
+    pub original_filename: Option<String>,
+    #[serde(default)]
+    pub mime_type: Option<String>,
+    #[serde(default)]
+    pub duration_ms: u64,
+    #[serde(default)]
+    pub average_speed: u64,
+    #[serde(default)]
+    pub status: DownloadStatus,
+    #[serde(default)]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+    // 从压缩包解压得到的文件记录所属压缩包的记录 ID
+    #[serde(default)]
+    pub extracted_from: Option<String>,
+    // 调用方提供的校验值，完成后由处理流程校验
+    #[serde(default)]
+    pub expected_sha256: Option<String>,
+    #[serde(default)]
+    pub post_processing: Vec<pipeline::StepStatus>,
+    #[serde(default)]
+    pub media: Option<media::MediaInfo>,
+}
+
+struct DownloadJob {
+    // 重试时沿用原记录 ID，新下载使用任务 ID
+    record_id: Option<String>,
+    url: String,
+    original_filename: Option<String>,
+    // 调用方没有提供文件名，完成后改用 Content-Disposition 中的名字
+    derive_filename: bool,
+    reservation: FilenameReservation,
+    sender_name: Option<String>,
+    sender_id: Option<String>,
+    idempotency_key: Option<String>,
+    expected_sha256: Option<String>,
+}
+
+impl DownloadJob {
+    fn record(&self, task_id: u64) -> DownloadRecord {
+        DownloadRecord {
+            id: self
+                .record_id
+                .clone()
+                .unwrap_or_else(|| task_id.to_string()),
+            filename: self.reservation.filename.clone(),
+            path: self.reservation.file_path.to_string_lossy().to_string(),
+            size: 0,
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as i64,
+            sha256: None,
+            missing: false,
+            url: self.url.clone(),
+            server: server_origin(&self.url),
+            sender_name: self.sender_name.clone(),
+            sender_id: self.sender_id.clone(),
+            original_filename: self.original_filename.clone(),
+            mime_type: None,
+            duration_ms: 0,
+            average_speed: 0,
+            status: DownloadStatus::Completed,
+            error: None,
+            idempotency_key: self.idempotency_key.clone(),
+            extracted_from: None,
+            expected_sha256: self.expected_sha256.clone(),
+            post_processing: Vec::new(),
+            media: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum DownloadError {
+    // 用户主动取消，keep_partial 为 false 时删除已下载的 .part 文件
+    Cancelled { keep_partial: bool },
+    InsufficientStorage { required: u64, available: u64 },
+    QuotaExceeded { quota: u64, used: u64 },
+    // 连接失败、传输中断或服务器 5xx，可以自动续传
+    Network(String),
+    // 超过 idle_timeout 没有收到任何数据
+    Stalled { seconds: u64 },
+    TimedOut { seconds: u64 },
+    Failed(String),
+}
+
+impl DownloadError {
+    fn kind(&self) -> &'static str {
+        match self {
+            DownloadError::Cancelled { .. } => "cancelled",
+            DownloadError::InsufficientStorage { .. } => "insufficient_storage",
+            DownloadError::QuotaExceeded { .. } => "quota_exceeded",
+            DownloadError::Network(_) => "network",
+            DownloadError::Stalled { .. } => "stalled",
+            DownloadError::TimedOut { .. } => "timed_out",
+            DownloadError::Failed(_) => "failed",
+        }
+    }
+
+    fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            DownloadError::Network(_) | DownloadError::Stalled { .. }
+        )
+    }
+}
+
+impl std::fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DownloadError::Cancelled { .. } => write!(f, "Download cancelled"),
+            DownloadError::InsufficientStorage {
+                required,
+                available,
+            } => write!(
+                f,
+                "Insufficient storage: {} bytes required, {} bytes available",
+                required, available
+            ),
+            DownloadError::QuotaExceeded { quota, used } => write!(
+                f,
+                "Download quota exceeded: {} of {} bytes used",
+                used, quota
+            ),
+            DownloadError::Network(e) => write!(f, "Network error: {}", e),
+            DownloadError::Stalled { seconds } => {
+                write!(f, "Download stalled: no data received for {} seconds", seconds)
+            }
+            DownloadError::TimedOut { seconds } => {
+                write!(f, "Download timed out after {} seconds", seconds)
+            }
+            DownloadError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<String> for DownloadError {
+    fn from(e: String) -> Self {
+        DownloadError::Failed(e)
+    }
+}
+
+// 大小未知的下载每写入这么多字节重新检查一次剩余空间和配额
+const SPACE_CHECK_INTERVAL: u64 = 8 * 1024 * 1024;
+
+// 所有进行中的下载预计最终占用的字节数，并发下载按它与已完成的下载共同计算配额
+type CommittedBytes = Arc<std::sync::Mutex<u64>>;
+
+struct StorageLimits {
+    quota: Option<u64>,
+    store: DownloadStore,
+    committed: CommittedBytes,
+    // 本任务计入 committed 的部分，任务结束时释放
+    reserved: std::sync::Mutex<u64>,
+}
+
+impl StorageLimits {
+    fn new(quota: Option<u64>, store: DownloadStore, committed: CommittedBytes) -> Self {
+        Self {
+            quota,
+            store,
+            committed,
+            reserved: std::sync::Mutex::new(0),
+        }
+    }
+
+    // needed 为接下来还要写入的字节数，projected 为本任务最终会占用的总字节数
+    async fn check(&self, dir: &Path, needed: u64, projected: u64) -> Result<(), DownloadError> {
+        let quota = self.quota;
+        let store = self.store.clone();
+        let dir = dir.to_path_buf();
+        let (completed, available) = blocking(move || {
+            let completed = if quota.is_some() { store.total_size()? } else { 0 };
+            let available = fs4::available_space(&dir)
+                .map_err(|e| format!("Failed to query free space: {}", e))?;
+            Ok((completed, available))
+        })
+        .await?;
+
+        if let Some(quota) = quota {
+            let mut committed = self.committed.lock().unwrap();
+            let mut reserved = self.reserved.lock().unwrap();
+
+            let used = completed.saturating_add(committed.saturating_sub(*reserved));
+            if used.saturating_add(projected) > quota {
+                return Err(DownloadError::QuotaExceeded { quota, used });
+            }
+            *committed = committed.saturating_sub(*reserved) + projected;
+            *reserved = projected;
+        }
+
+        if available < needed.saturating_add(settings::MIN_FREE_SPACE) {
+            return Err(DownloadError::InsufficientStorage {
+                required: needed,
+                available,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for StorageLimits {
+    fn drop(&mut self) {
+        let reserved = *self.reserved.get_mut().unwrap_or_else(|e| e.into_inner());
+        if let Ok(mut committed) = self.committed.lock() {
+            *committed = committed.saturating_sub(reserved);
+        }
+    }
+}
+
+struct TransferOptions {
+    storage: StorageLimits,
+    speed_limiter: SpeedLimiter,
+    progress_interval: std::time::Duration,
+    http: HttpClient,
+    idle_timeout: std::time::Duration,
+    // 按实际传输时间计算，暂停和重试等待不计入
+    total_timeout: Option<std::time::Duration>,
+    max_retries: u32,
+}
+
+enum TransferOutcome {
+    Completed {
+        total_size: u64,
+        mime_type: Option<String>,
+        disposition_name: Option<String>,
+    },
+    // 暂停时已关闭连接，恢复时重新发起 Range 请求
+    Paused,
+}
+
+// 跨越暂停前后多次连接保留的任务状态
+struct TransferSession {
+    control_rx: mpsc::UnboundedReceiver<DownloadControl>,
+    tracker: ProgressTracker,
+    // 单任务限速，与全局限速同时生效，取两者中更长的等待时间
+    limiter: TokenBucket,
+    snapshot: SharedSnapshot,
+}
+
+impl TransferSession {
+    fn emit_progress(
+        &self,
+        app: &AppHandle,
+        task_id: u64,
+        received: u64,
+        total: u64,
+        state: TransferState,
+    ) {
+        let progress = DownloadProgress {
+            id: task_id,
+            received,
+            total,
+            speed: self.tracker.speed(),
+            eta: self.tracker.eta(received, total),
+            elapsed: self.tracker.active_duration().as_millis() as u64,
+            state,
+        };
+
+        update_snapshot(app, &self.snapshot, |snapshot| {
+            snapshot.received = progress.received;
+            snapshot.total = progress.total;
+            snapshot.speed = progress.speed;
+            snapshot.eta = progress.eta;
+            snapshot.state = progress.state;
+            snapshot.error = None;
+        });
+        app.emit("download-progress", progress).unwrap_or(());
+    }
+
+    // 暂停期间没有连接，只等待控制指令
+    async fn wait_for_resume(&mut self) -> Result<(), DownloadError> {
+        loop {
+            match self.control_rx.recv().await {
+                Some(DownloadControl::Resume) => return Ok(()),
+                Some(DownloadControl::Pause) => {}
+                Some(DownloadControl::SpeedLimit(limit)) => self.limiter.set_rate(limit),
+                Some(DownloadControl::Cancel { keep_partial }) => {
+                    return Err(DownloadError::Cancelled { keep_partial })
+                }
+                None => return Err(DownloadError::Cancelled { keep_partial: true }),
+            }
+        }
+    }
+
+    // 重试前等待一段时间，期间仍响应控制指令；返回 true 表示用户在等待期间暂停了任务
+    async fn backoff(
+        &mut self,
+        app: &AppHandle,
+        task_id: u64,
+        delay: std::time::Duration,
+    ) -> Result<bool, DownloadError> {
+        let sleep = tokio::time::sleep(delay);
+        tokio::pin!(sleep);
+
+        loop {
+            tokio::select! {
+                _ = &mut sleep => return Ok(false),
+                control = self.control_rx.recv() => match control {
+                    Some(DownloadControl::Pause) => {
+                        update_snapshot(app, &self.snapshot, |snapshot| {
+                            snapshot.state = TransferState::Paused;
+                        });
+                        app.emit("download-paused", serde_json::json!({ "id": task_id })).unwrap_or(());
+                        return Ok(true);
+                    }
+                    Some(DownloadControl::Resume) => {}
+                    Some(DownloadControl::SpeedLimit(limit)) => self.limiter.set_rate(limit),
+                    Some(DownloadControl::Cancel { keep_partial }) => {
+                        return Err(DownloadError::Cancelled { keep_partial })
+                    }
+                    None => return Err(DownloadError::Cancelled { keep_partial: true }),
+                },
+            }
+        }
+    }
+}
+
+// 1、2、4、8、16、30、30... 秒
+fn retry_delay(attempt: u32) -> std::time::Duration {
+    std::time::Duration::from_secs((1u64 << attempt.saturating_sub(1).min(5)).min(30))
 }
 
 #[derive(Clone)]
 pub(crate) enum DownloadControl {
     Pause,
     Resume,
-    Cancel,
+    Cancel { keep_partial: bool },
+    SpeedLimit(Option<u64>),
 }
 
 pub struct ActiveDownload {
     pub control_tx: mpsc::UnboundedSender<DownloadControl>,
+    pub temp_path: PathBuf,
+    pub idempotency_key: Option<String>,
+    snapshot: SharedSnapshot,
 }
 
 pub struct DownloadState {
-    pub records: Arc<Mutex<Vec<DownloadRecord>>>,
+    pub store: DownloadStore,
     pub active_downloads: Arc<Mutex<HashMap<u64, ActiveDownload>>>,
+    pub settings: Arc<Mutex<DownloadSettings>>,
+    speed_limiter: SpeedLimiter,
+    http: HttpClient,
+    reserved_names: ReservedNames,
+    last_task_id: Arc<AtomicU64>,
+    shared_files: share::SharedFiles,
+    committed_bytes: CommittedBytes,
+    hooks: Arc<Vec<Box<dyn pipeline::PostDownloadHook>>>,
+    // 冲突策略为 ask 时等待用户选择的下载，编号与任务 ID 共用计数器
+    conflicts: Arc<Mutex<HashMap<u64, oneshot::Sender<ConflictPolicy>>>>,
 }
 
 impl Clone for DownloadState {
     fn clone(&self) -> Self {
         Self {
-            records: Arc::clone(&self.records),
+            store: self.store.clone(),
             active_downloads: Arc::clone(&self.active_downloads),
+            settings: Arc::clone(&self.settings),
+            speed_limiter: self.speed_limiter.clone(),
+            http: self.http.clone(),
+            reserved_names: Arc::clone(&self.reserved_names),
+            last_task_id: Arc::clone(&self.last_task_id),
+            shared_files: Arc::clone(&self.shared_files),
+            committed_bytes: Arc::clone(&self.committed_bytes),
+            hooks: Arc::clone(&self.hooks),
+            conflicts: Arc::clone(&self.conflicts),
         }
     }
 }
 
-fn get_unique_filename(dir: &PathBuf, filename: &str, existing_records: &[DownloadRecord]) -> String {
-    // 检查文件名是否在缓存或文件系统中已存在
-    let is_name_taken = |name: &str| -> bool {
-        // 检查缓存中是否存在
-        let in_cache = existing_records.iter().any(|r| r.filename == name);
-        // 检查文件系统中是否存在
-        let in_filesystem = dir.join(name).exists();
-        in_cache || in_filesystem
+impl DownloadState {
+    pub fn new(store: DownloadStore, settings: DownloadSettings) -> Result<Self, String> {
+        // 以已有记录中最大的 ID 为起点，避免时钟回拨后与历史记录冲突
+        let last_task_id = store.max_task_id()?;
+
+        Ok(Self {
+            store,
+            active_downloads: Arc::new(Mutex::new(HashMap::new())),
+            speed_limiter: SpeedLimiter::new(settings.speed_limit),
+            http: HttpClient::new(&settings)?,
+            settings: Arc::new(Mutex::new(settings)),
+            reserved_names: Default::default(),
+            last_task_id: Arc::new(AtomicU64::new(last_task_id)),
+            shared_files: Default::default(),
+            committed_bytes: Default::default(),
+            hooks: Arc::new(pipeline::default_hooks()),
+            conflicts: Default::default(),
+        })
+    }
+
+    // 基于毫秒时间戳，但保证严格递增，同一毫秒内启动的多个下载也不会冲突
+    fn next_task_id(&self) -> u64 {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+
+        let mut last = self.last_task_id.load(Ordering::Relaxed);
+        loop {
+            let next = now.max(last + 1);
+            match self.last_task_id.compare_exchange_weak(
+                last,
+                next,
+                Ordering::SeqCst,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return next,
+                Err(actual) => last = actual,
+            }
+        }
+    }
+}
+
+pub fn init_store(app: &AppHandle) -> Result<DownloadStore, String> {
+    let app_data = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    std::fs::create_dir_all(&app_data).map_err(|e| e.to_string())?;
+
+    let db_path = app_data.join("downloads.db");
+    let backup_path = app_data.join("downloads.db.bak");
+
+    // 从空库开始时不能用它覆盖备份，备份可能仍能手动恢复
+    let mut fresh = false;
+
+    let store = match DownloadStore::open(&db_path) {
+        Ok(store) => store,
+        Err(OpenError::Other(e)) => {
+            // 文件本身完好（例如降级后版本过新、被其他进程锁定），不做任何改动，本次运行只在内存中记录
+            log::error!("{}, keeping download history in memory for this session", e);
+            return DownloadStore::open_in_memory();
+        }
+        Err(OpenError::Corrupt(e)) => {
+            // 数据库损坏时先移走，再尝试用上一代备份恢复，都失败才从空库开始
+            log::error!("{}, restoring from backup", e);
+            let corrupt_path = app_data.join(format!(
+                "downloads.db.corrupt-{}",
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs()
+            ));
+            std::fs::rename(&db_path, &corrupt_path).map_err(|e| e.to_string())?;
+            let _ = std::fs::remove_file(app_data.join("downloads.db-wal"));
+            let _ = std::fs::remove_file(app_data.join("downloads.db-shm"));
+
+            let restored = if backup_path.exists() {
+                std::fs::copy(&backup_path, &db_path).map_err(|e| e.to_string())?;
+                DownloadStore::open(&db_path)
+                    .map_err(|e| log::error!("Backup is unusable ({}), starting with empty history", e))
+                    .ok()
+            } else {
+                None
+            };
+
+            match restored {
+                Some(store) => store,
+                None => {
+                    let _ = std::fs::remove_file(&db_path);
+                    fresh = true;
+                    DownloadStore::open(&db_path).map_err(|e| e.to_string())?
+                }
+            }
+        }
     };
 
-    if !is_name_taken(filename) {
-        return filename.to_string();
+    if !fresh {
+        if let Err(e) = store.backup_to(&backup_path) {
+            log::warn!("{}", e);
+        }
     }
 
-    let path = PathBuf::from(filename);
-    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
-    let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("");
+    match store.import_legacy_json(&app_data.join("downloads.json")) {
+        Ok(0) => {}
+        Ok(count) => log::info!("Imported {} download records from downloads.json", count),
+        Err(e) => log::error!("Failed to import downloads.json: {}", e),
+    }
+
+    Ok(store)
+}
+
+pub fn load_settings(app: &AppHandle) -> DownloadSettings {
+    settings::load(app)
+}
+
+async fn download_root(app: &AppHandle, download_state: &DownloadState) -> Result<PathBuf, String> {
+    download_state.settings.lock().await.download_root(app)
+}
+
+// 下载目录通常与浏览器等其他程序共用，使用专属后缀，清理时不会误删它们的临时文件
+const TEMP_SUFFIX: &str = ".zher.part";
+
+fn temp_path_for(file_path: &Path) -> PathBuf {
+    let mut name = file_path.file_name().unwrap_or_default().to_os_string();
+    name.push(TEMP_SUFFIX);
+    file_path.with_file_name(name)
+}
+
+fn server_origin(url: &str) -> Option<String> {
+    url::Url::parse(url)
+        .ok()
+        .map(|u| u.origin().ascii_serialization())
+        .filter(|origin| origin != "null")
+}
+
+// 数据库和文件系统调用都是同步的，在异步命令和下载任务中通过它放到阻塞线程执行
+async fn blocking<T, F>(f: F) -> Result<T, String>
+where
+    F: FnOnce() -> Result<T, String> + Send + 'static,
+    T: Send + 'static,
+{
+    tauri::async_runtime::spawn_blocking(f)
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+// 预留一个不与现有记录、文件及其他进行中下载冲突的文件名，并按冲突策略处理已存在的文件
+async fn reserve_new_file(
+    app: &AppHandle,
+    download_state: &DownloadState,
+    url: &str,
+    filename: &str,
+    sender_name: Option<&str>,
+    policy: Option<ConflictPolicy>,
+) -> Result<FilenameReservation, String> {
+    let (download_path, policy) = {
+        let settings = download_state.settings.lock().await;
+        let root = settings.download_root(app)?;
+        (
+            settings.target_dir(&root, &mime::from_name(filename), sender_name, url),
+            policy.unwrap_or(settings.conflict_policy),
+        )
+    };
 
-    let mut counter = 1;
+    let dir = download_path.clone();
+    blocking(move || settings::validate_dir(&dir)).await?;
+
+    let mut policy = policy;
     loop {
-        let new_name = if ext.is_empty() {
-            format!("{}({})", stem, counter)
-        } else {
-            format!("{}({}).{}", stem, counter, ext)
+        let reserved_names = Arc::clone(&download_state.reserved_names);
+        let store = download_state.store.clone();
+        let dir = download_path.clone();
+        let name = filename.to_string();
+        let reservation = blocking(move || {
+            filename::reserve_filename(&reserved_names, &dir, &name, &store, policy)
+        })
+        .await?;
+
+        let suggested = match reservation {
+            Reservation::Reserved(reservation) => return Ok(reservation),
+            Reservation::Conflict { suggested } => suggested,
         };
 
-        if !is_name_taken(&new_name) {
-            return new_name;
+        if policy == ConflictPolicy::Ask {
+            policy = ask_conflict_policy(app, download_state, url, filename, &suggested).await;
+            if policy != ConflictPolicy::Skip {
+                continue;
+            }
         }
-        counter += 1;
+
+        app.emit(
+            "download-skipped",
+            serde_json::json!({ "url": url, "filename": filename, "suggested": suggested }),
+        )
+        .unwrap_or(());
+        return Err(format!("File already exists: {}", filename));
+    }
+}
+
+// 前端收到 download-conflict 后调用 resolve_download_conflict 回复；超时或没有回复时跳过
+async fn ask_conflict_policy(
+    app: &AppHandle,
+    download_state: &DownloadState,
+    url: &str,
+    filename: &str,
+    suggested: &str,
+) -> ConflictPolicy {
+    const ANSWER_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(300);
+
+    let id = download_state.next_task_id();
+    let (tx, rx) = oneshot::channel();
+    download_state.conflicts.lock().await.insert(id, tx);
+
+    app.emit(
+        "download-conflict",
+        serde_json::json!({ "id": id, "url": url, "filename": filename, "suggested": suggested }),
+    )
+    .unwrap_or(());
+
+    let answer = tokio::time::timeout(ANSWER_TIMEOUT, rx).await;
+    download_state.conflicts.lock().await.remove(&id);
+
+    match answer {
+        // 再次选择 ask 没有意义，按跳过处理
+        Ok(Ok(policy)) if policy != ConflictPolicy::Ask => policy,
+        _ => ConflictPolicy::Skip,
     }
 }
 
 #[tauri::command]
+pub async fn resolve_download_conflict(
+    id: u64,
+    policy: ConflictPolicy,
+    download_state: State<'_, DownloadState>,
+) -> Result<(), String> {
+    let tx = download_state
+        .conflicts
+        .lock()
+        .await
+        .remove(&id)
+        .ok_or("Conflict not found")?;
+    tx.send(policy).map_err(|_| "Conflict already resolved".to_string())
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn download_file(
     app: AppHandle,
     url: String,
     filename: String,
+    sender_name: Option<String>,
+    sender_id: Option<String>,
+    idempotency_key: Option<String>,
+    conflict_policy: Option<ConflictPolicy>,
+    expected_sha256: Option<String>,
     download_state: State<'_, DownloadState>,
-) -> Result<String, String> {
-    #[cfg(target_os = "android")]
-    let download_path = {
-        use std::path::PathBuf;
-        PathBuf::from("/storage/emulated/0/Download")
+) -> Result<u64, String> {
+    if let Some(key) = &idempotency_key {
+        // 同一条消息正在下载时直接返回该任务，不再预留新文件名，也不触发冲突处理
+        if let Some(id) = find_active_by_key(&*download_state.active_downloads.lock().await, key) {
+            return Ok(id);
+        }
+
+        let store = download_state.store.clone();
+        let key = key.clone();
+        let completed = blocking(move || {
+            let Some(record) = store.find_by_idempotency_key(&key)? else {
+                return Ok(None);
+            };
+            if record.status != DownloadStatus::Completed {
+                return Ok(None);
+            }
+            // missing 只在启动时刷新，文件可能在运行期间被外部删除
+            if Path::new(&record.path).is_file() {
+                return Ok(Some(record));
+            }
+            if !record.missing {
+                store.update(&record.id, |r| {
+                    r.missing = true;
+                    Ok(())
+                })?;
+            }
+            Ok(None)
+        })
+        .await?;
+
+        if let Some(id) = completed.and_then(|record| record.id.parse::<u64>().ok()) {
+            return Ok(id);
+        }
+    }
+
+    let derive_filename = filename.trim().is_empty();
+    let filename = filename::sanitize_filename(&filename);
+    let reservation = reserve_new_file(
+        &app,
+        download_state.inner(),
+        &url,
+        &filename,
+        sender_name.as_deref(),
+        conflict_policy,
+    )
+    .await?;
+
+    let job = DownloadJob {
+        record_id: None,
+        url,
+        original_filename: (filename != reservation.filename).then_some(filename),
+        derive_filename,
+        reservation,
+        sender_name,
+        sender_id,
+        idempotency_key,
+        expected_sha256: expected_sha256.filter(|h| !h.trim().is_empty()),
     };
 
-    #[cfg(not(target_os = "android"))]
-    let download_path = app.path().download_dir().map_err(|e| e.to_string())?;
+    Ok(start_download(&app, download_state.inner(), job).await)
+}
 
-    tokio::fs::create_dir_all(&download_path)
-        .await
-        .map_err(|e| format!("Failed to create download dir: {}", e))?;
+#[tauri::command]
+pub async fn retry_download(
+    app: AppHandle,
+    id: String,
+    download_state: State<'_, DownloadState>,
+) -> Result<u64, String> {
+    let store = download_state.store.clone();
+    let record = blocking(move || store.get(&id))
+        .await?
+        .ok_or("Download not found")?;
+
+    if record.status == DownloadStatus::Completed {
+        return Err("Download already completed".to_string());
+    }
+    if record.url.is_empty() {
+        return Err("Source URL unknown".to_string());
+    }
+
+    // 本次运行中暂停的任务仍在等待恢复指令，直接恢复；重启后则重新发起续传
+    if record.status == DownloadStatus::Paused {
+        let temp_path = temp_path_for(Path::new(&record.path));
+        let active = download_state.active_downloads.lock().await;
+        if let Some((task_id, download)) = active.iter().find(|(_, d)| d.temp_path == temp_path) {
+            download
+                .control_tx
+                .send(DownloadControl::Resume)
+                .map_err(|e| e.to_string())?;
+            return Ok(*task_id);
+        }
+    }
 
-    // 获取现有的下载记录用于检查文件名冲突
-    let existing_records = {
-        let records = download_state.records.lock().await;
-        records.clone()
+    let reservation =
+        filename::reserve_existing(&download_state.reserved_names, Path::new(&record.path))?;
+
+    // 保留原记录 ID 和 .part 文件，完成后覆盖失败记录
+    let job = DownloadJob {
+        record_id: Some(record.id),
+        url: record.url,
+        original_filename: record.original_filename,
+        derive_filename: false,
+        reservation,
+        sender_name: record.sender_name,
+        sender_id: record.sender_id,
+        idempotency_key: record.idempotency_key,
+        expected_sha256: record.expected_sha256,
     };
 
-    let unique_filename = get_unique_filename(&download_path, &filename, &existing_records);
-    let file_path = download_path.join(&unique_filename);
-    let temp_path = download_path.join(format!("{}.part", unique_filename));
+    Ok(start_download(&app, download_state.inner(), job).await)
+}
 
-    let task_id = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_millis() as u64;
+#[tauri::command]
+pub async fn redownload(
+    app: AppHandle,
+    id: String,
+    download_state: State<'_, DownloadState>,
+) -> Result<u64, String> {
+    let store = download_state.store.clone();
+    let record = blocking(move || store.get(&id))
+        .await?
+        .ok_or("Download not found")?;
 
-    let (control_tx, mut control_rx) = mpsc::unbounded_channel();
+    if record.url.is_empty() {
+        return Err("Source URL unknown".to_string());
+    }
 
-    {
+    let filename = record.original_filename.unwrap_or(record.filename);
+    let reservation = reserve_new_file(
+        &app,
+        download_state.inner(),
+        &record.url,
+        &filename,
+        record.sender_name.as_deref(),
+        Some(ConflictPolicy::Rename),
+    )
+    .await?;
+
+    let job = DownloadJob {
+        record_id: None,
+        url: record.url,
+        original_filename: (filename != reservation.filename).then_some(filename),
+        derive_filename: false,
+        reservation,
+        sender_name: record.sender_name,
+        sender_id: record.sender_id,
+        idempotency_key: None,
+        expected_sha256: record.expected_sha256,
+    };
+
+    Ok(start_download(&app, download_state.inner(), job).await)
+}
+
+fn find_active_by_key(active: &HashMap<u64, ActiveDownload>, key: &str) -> Option<u64> {
+    active
+        .iter()
+        .find(|(_, d)| d.idempotency_key.as_deref() == Some(key))
+        .map(|(id, _)| *id)
+}
+
+async fn start_download(app: &AppHandle, download_state: &DownloadState, job: DownloadJob) -> u64 {
+    let (control_tx, control_rx) = mpsc::unbounded_channel();
+
+    // 检查与插入在同一把锁内完成，重复点击同一条消息只会得到同一个任务
+    let (task_id, snapshot) = {
         let mut active = download_state.active_downloads.lock().await;
+
+        if let Some(id) = job
+            .idempotency_key
+            .as_deref()
+            .and_then(|key| find_active_by_key(&active, key))
+        {
+            return id;
+        }
+
+        let task_id = download_state.next_task_id();
+        let snapshot = Arc::new(std::sync::Mutex::new(DownloadSnapshot {
+            id: task_id,
+            record_id: job.record_id.clone().unwrap_or_else(|| task_id.to_string()),
+            url: job.url.clone(),
+            filename: job.reservation.filename.clone(),
+            path: job.reservation.file_path.to_string_lossy().to_string(),
+            received: 0,
+            total: 0,
+            speed: 0,
+            eta: None,
+            state: TransferState::Pending,
+            error: None,
+        }));
         active.insert(
             task_id,
             ActiveDownload {
                 control_tx: control_tx.clone(),
+                temp_path: job.reservation.temp_path.clone(),
+                idempotency_key: job.idempotency_key.clone(),
+                snapshot: Arc::clone(&snapshot),
             },
         );
-    }
+        (task_id, snapshot)
+    };
 
+    // 改名在阻塞线程中进行，预留的文件名要一直保持到任务结束
+    let job = Arc::new(job);
     let app_clone = app.clone();
-    let download_state_clone = download_state.inner().clone();
-    let file_path_clone = file_path.clone();
-    let temp_path_clone = temp_path.clone();
+    let download_state_clone = download_state.clone();
 
     tokio::spawn(async move {
-        let result = download_task(
-            app_clone.clone(),
-            task_id,
-            url,
-            temp_path_clone.clone(),
-            &mut control_rx,
-        )
-        .await;
+        let options = {
+            let settings = download_state_clone.settings.lock().await;
+            TransferOptions {
+                storage: StorageLimits::new(
+                    settings.max_total_bytes,
+                    download_state_clone.store.clone(),
+                    Arc::clone(&download_state_clone.committed_bytes),
+                ),
+                speed_limiter: download_state_clone.speed_limiter.clone(),
+                progress_interval: settings.progress_interval(),
+                http: download_state_clone.http.clone(),
+                idle_timeout: settings.idle_timeout(),
+                total_timeout: settings.total_timeout(),
+                max_retries: settings.max_retries(),
+            }
+        };
 
-        match result {
-            Ok(total_size) => {
-                if let Err(_) = tokio::fs::rename(&temp_path_clone, &file_path_clone).await {
+        let mut session = TransferSession {
+            control_rx,
+            tracker: ProgressTracker::default(),
+            limiter: TokenBucket::new(None),
+            snapshot,
+        };
+
+        let mut attempts = 0;
+        let mut transferred_before_failure = 0;
+
+        let result = loop {
+            let paused = match download_task(
+                app_clone.clone(),
+                task_id,
+                job.url.clone(),
+                job.reservation.temp_path.clone(),
+                &options,
+                &mut session,
+            )
+            .await
+            {
+                Ok(TransferOutcome::Completed {
+                    total_size,
+                    mime_type,
+                    disposition_name,
+                }) => break Ok((total_size, mime_type, disposition_name)),
+                Ok(TransferOutcome::Paused) => true,
+                Err(e) if e.is_retryable() => {
+                    // 上次失败后有新数据写入说明连接曾经恢复，重新计数
+                    if session.tracker.transferred() > transferred_before_failure {
+                        attempts = 0;
+                    }
+                    transferred_before_failure = session.tracker.transferred();
+
+                    if attempts >= options.max_retries {
+                        break Err(e);
+                    }
+                    attempts += 1;
+
+                    let delay = retry_delay(attempts);
+                    log::warn!(
+                        "Download {} interrupted ({}), retrying in {:?} ({}/{})",
+                        task_id,
+                        e,
+                        delay,
+                        attempts,
+                        options.max_retries
+                    );
+                    session.tracker.pause();
+                    update_snapshot(&app_clone, &session.snapshot, |snapshot| {
+                        snapshot.state = TransferState::Pending;
+                        snapshot.error = Some(e.to_string());
+                    });
                     app_clone
                         .emit(
-                            "download-failed",
-                            serde_json::json!({ "id": task_id, "error": "Failed to save file" }),
+                            "download-retrying",
+                            serde_json::json!({
+                                "id": task_id,
+                                "attempt": attempts,
+                                "error": e.to_string(),
+                                "kind": e.kind(),
+                                "delay": delay.as_millis() as u64,
+                            }),
                         )
                         .unwrap_or(());
-                } else {
-                    let record = DownloadRecord {
-                        id: task_id.to_string(),
-                        filename: unique_filename.clone(),
-                        path: file_path_clone.to_string_lossy().to_string(),
-                        size: total_size,
-                        timestamp: std::time::SystemTime::now()
-                            .duration_since(std::time::UNIX_EPOCH)
-                            .unwrap()
-                            .as_millis() as i64,
-                    };
-
-                    {
-                        let mut records = download_state_clone.records.lock().await;
-                        records.push(record.clone());
-                        let records_clone = records.clone();
-                        drop(records);
-
-                        let app_clone2 = app_clone.clone();
-                        tokio::spawn(async move {
-                            save_downloads(&app_clone2, &records_clone).await;
-                        });
+
+                    match session.backoff(&app_clone, task_id, delay).await {
+                        Ok(paused) => paused,
+                        Err(e) => break Err(e),
                     }
+                }
+                Err(e) => break Err(e),
+            };
 
-                    app_clone
-                        .emit("download-completed", serde_json::json!({ "id": task_id }))
-                        .unwrap_or(());
+            if !paused {
+                continue;
+            }
+
+            // 持久化暂停状态和已下载的偏移量，应用重启后仍可通过 retry_download 续传
+            let mut record = job.record(task_id);
+            record.status = DownloadStatus::Paused;
+            record.size = tokio::fs::metadata(&job.reservation.temp_path)
+                .await
+                .map(|m| m.len())
+                .unwrap_or(0);
+            let store = download_state_clone.store.clone();
+            if let Err(e) = blocking(move || store.upsert(&record)).await {
+                log::error!("Failed to save paused download {}: {}", task_id, e);
+            }
+
+            if let Err(e) = session.wait_for_resume().await {
+                break Err(e);
+            }
+            update_snapshot(&app_clone, &session.snapshot, |snapshot| {
+                snapshot.state = TransferState::Pending;
+            });
+            app_clone
+                .emit("download-resumed", serde_json::json!({ "id": task_id }))
+                .unwrap_or(());
+        };
+
+        let mut record = job.record(task_id);
+        // 记录保存后再通知前端，前端收到事件后会立即重新读取列表
+        let event: (&str, serde_json::Value);
+
+        match result {
+            Ok((total_size, mime_type, disposition_name)) => {
+                // 先校验再改名，校验失败的内容不会以最终文件名出现
+                let hash_path = job.reservation.temp_path.clone();
+                let sha256 = tauri::async_runtime::spawn_blocking(move || {
+                    reconcile::hash_file(&hash_path)
+                })
+                .await
+                .ok()
+                .and_then(|r| r.ok());
+
+                let verify_error =
+                    checksum_error(job.expected_sha256.as_deref(), sha256.as_deref());
+                if job.expected_sha256.is_some() {
+                    record.post_processing.push(pipeline::StepStatus {
+                        step: "verify".to_string(),
+                        state: if verify_error.is_some() {
+                            pipeline::StepState::Failed
+                        } else {
+                            pipeline::StepState::Succeeded
+                        },
+                        message: verify_error.map(str::to_string),
+                    });
+                }
+
+                if let Some(error) = verify_error {
+                    log::error!("Download {} failed verification: {}", task_id, error);
+                    // 内容已损坏，续传没有意义，重试时从头下载
+                    let _ = tokio::fs::remove_file(&job.reservation.temp_path).await;
+                    record.status = DownloadStatus::Failed;
+                    record.error = Some(error.to_string());
+                    event = (
+                        "download-failed",
+                        serde_json::json!({ "id": task_id, "error": error, "kind": "verify" }),
+                    );
+                } else {
+                    let finalize_state = download_state_clone.clone();
+                    let finalize_job = Arc::clone(&job);
+                    let declared = mime_type.clone();
+                    let finalized = blocking(move || {
+                        finalize_file(
+                            &finalize_state,
+                            &finalize_job,
+                            disposition_name.as_deref(),
+                            declared.as_deref(),
+                        )
+                    })
+                    .await;
+                    match finalized {
+                        Err(e) => {
+                            log::error!("Failed to save download {}: {}", task_id, e);
+                            record.status = DownloadStatus::Failed;
+                            record.error = Some("Failed to save file".to_string());
+                            record.size = total_size;
+                            event = (
+                                "download-failed",
+                                serde_json::json!({ "id": task_id, "error": "Failed to save file" }),
+                            );
+                        }
+                        Ok((filename, file_path, resolved_mime)) => {
+                            if filename != job.reservation.filename {
+                                record
+                                    .original_filename
+                                    .get_or_insert_with(|| job.reservation.filename.clone());
+                                record.filename = filename;
+                                record.path = file_path.to_string_lossy().to_string();
+                                update_snapshot(&app_clone, &session.snapshot, |snapshot| {
+                                    snapshot.filename = record.filename.clone();
+                                    snapshot.path = record.path.clone();
+                                });
+                            }
+
+                            record.mime_type = Some(resolved_mime);
+
+                            let duration = session.tracker.active_duration();
+                            record.size = total_size;
+                            record.sha256 = sha256;
+                            record.duration_ms = duration.as_millis() as u64;
+                            record.average_speed =
+                                average_speed(session.tracker.transferred(), duration);
+
+                            event = ("download-completed", serde_json::json!({ "id": task_id }));
+                        }
+                    }
+                }
+            }
+            Err(DownloadError::Cancelled { keep_partial }) => {
+                // 用户取消不是错误，不记录 error，与网络失败区分开
+                record.status = DownloadStatus::Cancelled;
+                if !keep_partial {
+                    let _ = tokio::fs::remove_file(&job.reservation.temp_path).await;
                 }
+                record.size = tokio::fs::metadata(&job.reservation.temp_path)
+                    .await
+                    .map(|m| m.len())
+                    .unwrap_or(0);
+
+                event = (
+                    "download-cancelled",
+                    serde_json::json!({ "id": task_id, "keptPartial": record.size > 0 }),
+                );
             }
             Err(e) => {
-                app_clone
-                    .emit(
-                        "download-failed",
-                        serde_json::json!({ "id": task_id, "error": e }),
-                    )
-                    .unwrap_or(());
+                record.status = DownloadStatus::Failed;
+                record.error = Some(e.to_string());
+                // 保留 .part 文件以便重试时续传，size 记录已下载的部分
+                record.size = tokio::fs::metadata(&job.reservation.temp_path)
+                    .await
+                    .map(|m| m.len())
+                    .unwrap_or(0);
+
+                event = (
+                    "download-failed",
+                    serde_json::json!({ "id": task_id, "error": e.to_string(), "kind": e.kind() }),
+                );
+            }
+        }
+
+        update_snapshot(&app_clone, &session.snapshot, |snapshot| {
+            snapshot.state = match record.status {
+                DownloadStatus::Completed => TransferState::Completed,
+                DownloadStatus::Cancelled => TransferState::Cancelled,
+                _ => TransferState::Failed,
+            };
+            snapshot.error = record.error.clone();
+            snapshot.speed = 0;
+            snapshot.eta = None;
+        });
+
+        let store = download_state_clone.store.clone();
+        let saved = record.clone();
+        let result = blocking(move || {
+            store.upsert(&saved)?;
+            // 覆盖已有文件后，指向同一路径的旧记录已失效
+            if saved.status == DownloadStatus::Completed {
+                store.remove_other_with_path(&saved.path, &saved.id)?;
+            }
+            Ok(())
+        })
+        .await;
+        if let Err(e) = result {
+            log::error!("Failed to save download record {}: {}", record.id, e);
+        }
+        // 记录保存后大小已计入已完成的部分，释放本任务的配额预留，不必等后处理结束
+        drop(options);
+
+        download_state_clone
+            .active_downloads
+            .lock()
+            .await
+            .remove(&task_id);
+
+        app_clone.emit(event.0, event.1).unwrap_or(());
+
+        if record.status == DownloadStatus::Completed {
+            let settings = download_state_clone.settings.lock().await.clone();
+            let _ = tauri::async_runtime::spawn_blocking(move || {
+                let ctx = pipeline::HookContext {
+                    app: &app_clone,
+                    state: &download_state_clone,
+                    settings: &settings,
+                    record,
+                };
+                pipeline::run(&download_state_clone.hooks, ctx)
+            })
+            .await;
+        }
+    });
+
+    task_id
+}
+
+// 调用方提供了期望的 SHA-256 时校验，不一致说明文件损坏或被篡改
+fn checksum_error(expected: Option<&str>, actual: Option<&str>) -> Option<&'static str> {
+    let expected = expected?;
+    match actual {
+        Some(actual) if actual.eq_ignore_ascii_case(expected.trim()) => None,
+        Some(_) => Some("Checksum mismatch"),
+        None => Some("Failed to compute checksum"),
+    }
+}
+
+fn archive_kind(record: &DownloadRecord) -> Option<extract::ArchiveKind> {
+    let mime_type = record
+        .mime_type
+        .clone()
+        .unwrap_or_else(|| mime::resolve(Path::new(&record.path), None));
+    extract::ArchiveKind::detect(&record.filename, &mime_type)
+}
+
+// 解压到压缩包旁边的同名文件夹，并为每个文件创建一条记录；失败时删除已解压的内容
+fn extract_archive(
+    app: &AppHandle,
+    download_state: &DownloadState,
+    record: &DownloadRecord,
+) -> Result<Vec<DownloadRecord>, String> {
+    let kind = archive_kind(record).ok_or("Not a supported archive")?;
+    let archive = PathBuf::from(&record.path);
+
+    app.emit("extract-started", serde_json::json!({ "id": record.id }))
+        .unwrap_or(());
+
+    let result = extract::create_target_dir(&archive, kind).and_then(|target| {
+        let extracted = extract::extract(&archive, kind, &target, |index, total, file| {
+            app.emit(
+                "extract-progress",
+                serde_json::json!({ "id": record.id, "index": index, "total": total, "name": file.name }),
+            )
+            .unwrap_or(());
+        });
+
+        match extracted {
+            Ok(files) => {
+                let hashed = files
+                    .into_iter()
+                    .map(|file| {
+                        let sha256 = reconcile::hash_file(&file.path).ok();
+                        (file, sha256)
+                    })
+                    .collect::<Vec<_>>();
+                Ok((target, hashed))
             }
+            Err(e) => {
+                let _ = std::fs::remove_dir_all(&target);
+                Err(e)
+            }
+        }
+    });
+
+    let (target, files) = match result {
+        Ok(result) => result,
+        Err(e) => {
+            app.emit("extract-failed", serde_json::json!({ "id": record.id, "error": e }))
+                .unwrap_or(());
+            return Err(e);
+        }
+    };
+
+    let mut records = Vec::with_capacity(files.len());
+    for (file, sha256) in files {
+        let filename = file
+            .path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| file.name.clone());
+        let extracted = DownloadRecord {
+            id: download_state.next_task_id().to_string(),
+            filename,
+            path: file.path.to_string_lossy().to_string(),
+            size: file.size,
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as i64,
+            sha256,
+            missing: false,
+            url: record.url.clone(),
+            server: record.server.clone(),
+            sender_name: record.sender_name.clone(),
+            sender_id: record.sender_id.clone(),
+            original_filename: None,
+            mime_type: Some(mime::resolve(&file.path, None)),
+            duration_ms: 0,
+            average_speed: 0,
+            status: DownloadStatus::Completed,
+            error: None,
+            idempotency_key: None,
+            extracted_from: Some(record.id.clone()),
+            expected_sha256: None,
+            post_processing: Vec::new(),
+            media: None,
+        };
+        download_state.store.upsert(&extracted)?;
+        records.push(extracted);
+    }
+
+    app.emit(
+        "extract-completed",
+        serde_json::json!({
+            "id": record.id,
+            "dir": target.to_string_lossy(),
+            "count": records.len(),
+        }),
+    )
+    .unwrap_or(());
+
+    Ok(records)
+}
+
+// 确定最终文件名并把 .part 移到目标位置；文件名有变化时另外预留一个不冲突的名字
+fn finalize_file(
+    download_state: &DownloadState,
+    job: &DownloadJob,
+    disposition_name: Option<&str>,
+    declared: Option<&str>,
+) -> Result<(String, PathBuf, String), String> {
+    let reservation = &job.reservation;
+    let sniffed = mime::sniff_file(&reservation.temp_path);
+    let final_name = filename::resolve_final_name(
+        &reservation.filename,
+        job.derive_filename,
+        disposition_name,
+        sniffed,
+        declared,
+    );
+
+    let target = if final_name == reservation.filename {
+        None
+    } else {
+        let dir = reservation.file_path.parent().ok_or("Invalid path")?;
+        match filename::reserve_filename(
+            &download_state.reserved_names,
+            dir,
+            &final_name,
+            &download_state.store,
+            ConflictPolicy::Rename,
+        )? {
+            Reservation::Reserved(target) => Some(target),
+            Reservation::Conflict { .. } => None,
         }
+    };
 
-        download_state_clone
-            .active_downloads
-            .lock()
-            .await
-            .remove(&task_id);
-    });
+    // 新预留的 .part 占位文件在 target 释放时自动删除
+    let (filename, file_path) = match &target {
+        Some(target) => (target.filename.clone(), target.file_path.clone()),
+        None => (reservation.filename.clone(), reservation.file_path.clone()),
+    };
+    std::fs::rename(&reservation.temp_path, &file_path).map_err(|e| e.to_string())?;
+
+    // 与打开、分享时使用同一套判断，结果存入记录
+    let mime_type = mime::resolve(&file_path, declared);
+    Ok((filename, file_path, mime_type))
+}
 
-    Ok(task_id.to_string())
+fn average_speed(bytes: u64, duration: std::time::Duration) -> u64 {
+    let millis = duration.as_millis() as u64;
+    bytes
+        .saturating_mul(1000)
+        .checked_div(millis)
+        .unwrap_or(bytes)
 }
 
 async fn download_task(
@@ -206,13 +1420,9 @@ async fn download_task(
     task_id: u64,
     url: String,
     temp_path: PathBuf,
-    control_rx: &mut mpsc::UnboundedReceiver<DownloadControl>,
-) -> Result<u64, String> {
-    let client = reqwest::Client::builder()
-        .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36")
-        .build()
-        .map_err(|e| e.to_string())?;
-
+    options: &TransferOptions,
+    session: &mut TransferSession,
+) -> Result<TransferOutcome, DownloadError> {
     let existing_size = if temp_path.exists() {
         tokio::fs::metadata(&temp_path)
             .await
@@ -222,20 +1432,40 @@ async fn download_task(
         0
     };
 
-    let mut request = client.get(&url);
+    let mut request = options.http.get(&url);
     if existing_size > 0 {
         request = request.header("Range", format!("bytes={}-", existing_size));
     }
 
-    let response = request.send().await.map_err(|e| e.to_string())?;
+    let idle_secs = options.idle_timeout.as_secs();
+
+    // 连接建立后迟迟不返回响应头同样视为停滞
+    let response = tokio::time::timeout(options.idle_timeout, request.send())
+        .await
+        .map_err(|_| DownloadError::Stalled { seconds: idle_secs })?
+        .map_err(|e| DownloadError::Network(e.to_string()))?;
 
+    if response.status().is_server_error() {
+        return Err(DownloadError::Network(format!(
+            "Server responded with status: {}",
+            response.status()
+        )));
+    }
     if !response.status().is_success() {
         return Err(format!(
             "Download failed with status: {}",
             response.status()
-        ));
+        )
+        .into());
     }
 
+    // 服务器忽略 Range 时返回完整内容，需要从头写入
+    let existing_size = if response.status() == reqwest::StatusCode::PARTIAL_CONTENT {
+        existing_size
+    } else {
+        0
+    };
+
     let total_size = if let Some(content_range) = response.headers().get("content-range") {
         content_range
             .to_str()
@@ -247,6 +1477,33 @@ async fn download_task(
         response.content_length().unwrap_or(0)
     };
 
+    let mime_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty());
+
+    let disposition_name = response
+        .headers()
+        .get(reqwest::header::CONTENT_DISPOSITION)
+        .and_then(|v| filename::parse_content_disposition(v.as_bytes()));
+
+    // 大小已知时一次性检查，未知时先按一个检查间隔预留，之后边下载边检查
+    let download_dir = temp_path.parent().unwrap_or(Path::new("."));
+    if total_size > 0 {
+        options
+            .storage
+            .check(download_dir, total_size.saturating_sub(existing_size), total_size)
+            .await?;
+    } else {
+        options
+            .storage
+            .check(download_dir, SPACE_CHECK_INTERVAL, existing_size)
+            .await?;
+    }
+
     app.emit(
         "download-started",
         serde_json::json!({
@@ -264,7 +1521,9 @@ async fn download_task(
         .await
         .map_err(|e| e.to_string())?;
 
-    if existing_size > 0 {
+    if existing_size == 0 {
+        file.set_len(0).await.map_err(|e| e.to_string())?;
+    } else {
         file.seek(tokio::io::SeekFrom::End(0))
             .await
             .map_err(|e| e.to_string())?;
@@ -272,60 +1531,121 @@ async fn download_task(
 
     let mut stream = response.bytes_stream();
     let mut received = existing_size;
-    let mut paused = false;
     let mut last_progress_update = std::time::Instant::now();
+    let mut next_space_check = received + SPACE_CHECK_INTERVAL;
+    session.tracker.resume(received);
+    // 之前各次连接已用掉的传输时间从总时长中扣除
+    let deadline = options
+        .total_timeout
+        .map(|t| tokio::time::Instant::now() + t.saturating_sub(session.tracker.active_duration()));
+    session.emit_progress(&app, task_id, received, total_size, TransferState::Downloading);
+    let throttle = tokio::time::sleep(std::time::Duration::ZERO);
+    tokio::pin!(throttle);
+    let mut throttled = false;
+    // 每收到数据就推迟；限速等待期间不读取数据，不计入空闲时间
+    let idle = tokio::time::sleep(options.idle_timeout);
+    tokio::pin!(idle);
+    let total_timeout = tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now));
+    tokio::pin!(total_timeout);
 
     loop {
         tokio::select! {
             biased;
             
-            control = control_rx.recv() => {
+            control = session.control_rx.recv() => {
                 match control {
+                    // 返回时丢弃响应流，连接随之关闭，不会因长时间暂停被服务器超时断开
                     Some(DownloadControl::Pause) => {
-                        paused = true;
+                        session.tracker.pause();
                         file.flush().await.map_err(|e| e.to_string())?;
+                        session.emit_progress(&app, task_id, received, total_size, TransferState::Paused);
                         app.emit("download-paused", serde_json::json!({ "id": task_id })).unwrap_or(());
+                        return Ok(TransferOutcome::Paused);
                     }
-                    Some(DownloadControl::Resume) => {
-                        paused = false;
-                        app.emit("download-resumed", serde_json::json!({ "id": task_id })).unwrap_or(());
+                    Some(DownloadControl::Resume) => {}
+                    Some(DownloadControl::SpeedLimit(limit)) => {
+                        session.limiter.set_rate(limit);
                     }
-                    Some(DownloadControl::Cancel) | None => {
+                    Some(DownloadControl::Cancel { keep_partial }) => {
                         file.flush().await.map_err(|e| e.to_string())?;
-                        return Err("Download cancelled".to_string());
+                        return Err(DownloadError::Cancelled { keep_partial });
+                    }
+                    None => {
+                        file.flush().await.map_err(|e| e.to_string())?;
+                        return Err(DownloadError::Cancelled { keep_partial: true });
                     }
                 }
             }
-            item = stream.next(), if !paused => {
+            _ = &mut total_timeout, if deadline.is_some() => {
+                file.flush().await.map_err(|e| e.to_string())?;
+                return Err(DownloadError::TimedOut {
+                    seconds: options.total_timeout.unwrap_or_default().as_secs(),
+                });
+            }
+            _ = &mut throttle, if throttled => {
+                throttled = false;
+                idle.as_mut().reset(tokio::time::Instant::now() + options.idle_timeout);
+            }
+            item = stream.next(), if !throttled => {
                 match item {
                     Some(Ok(chunk)) => {
+                        idle.as_mut().reset(tokio::time::Instant::now() + options.idle_timeout);
                         file.write_all(&chunk).await.map_err(|e| e.to_string())?;
                         received += chunk.len() as u64;
+                        session.tracker.add_transferred(chunk.len() as u64);
+
+                        // 限速时暂停读取而不是阻塞，等待期间仍能响应暂停和取消
+                        let delay = options
+                            .speed_limiter
+                            .consume(chunk.len() as u64)
+                            .max(session.limiter.consume(chunk.len() as u64));
+                        if !delay.is_zero() {
+                            throttle
+                                .as_mut()
+                                .reset(tokio::time::Instant::now() + delay);
+                            throttled = true;
+                        }
+
+                        if total_size == 0 && received >= next_space_check {
+                            options
+                                .storage
+                                .check(download_dir, SPACE_CHECK_INTERVAL, received)
+                                .await?;
+                            next_space_check = received + SPACE_CHECK_INTERVAL;
+                        }
 
-                        // Update progress every 100ms to reduce overhead
-                        if last_progress_update.elapsed().as_millis() >= 100 {
-                            app.emit(
-                                "download-progress",
-                                DownloadProgress {
-                                    id: task_id,
-                                    received,
-                                    total: total_size,
-                                },
-                            )
-                            .unwrap_or(());
+                        // 按设置的间隔节流进度事件，减少前端开销
+                        if last_progress_update.elapsed() >= options.progress_interval {
+                            session.tracker.sample(received);
+                            session.emit_progress(&app, task_id, received, total_size, TransferState::Downloading);
                             last_progress_update = std::time::Instant::now();
                         }
                     }
                     Some(Err(e)) => {
                         file.flush().await.map_err(|e| e.to_string())?;
-                        return Err(e.to_string());
+                        return Err(DownloadError::Network(e.to_string()));
                     }
                     None => {
                         file.flush().await.map_err(|e| e.to_string())?;
-                        return Ok(total_size);
+                        session.tracker.sample(received);
+
+                        // 最后一次节流的进度可能被跳过，结束时总是补发一次 100%
+                        let total_size = if total_size > 0 { total_size } else { received };
+                        session.emit_progress(&app, task_id, received, total_size, TransferState::Completed);
+                        session.tracker.pause();
+
+                        return Ok(TransferOutcome::Completed {
+                            total_size,
+                            mime_type,
+                            disposition_name,
+                        });
                     }
                 }
             }
+            _ = &mut idle, if !throttled => {
+                file.flush().await.map_err(|e| e.to_string())?;
+                return Err(DownloadError::Stalled { seconds: idle_secs });
+            }
         }
     }
 }
@@ -364,16 +1684,20 @@ pub async fn resume_download(
     }
 }
 
+// 默认删除已下载的部分，keep_partial 为 true 时保留 .part 以便之后通过 retry_download 续传
 #[tauri::command]
 pub async fn cancel_download(
     task_id: u64,
+    keep_partial: Option<bool>,
     download_state: State<'_, DownloadState>,
 ) -> Result<(), String> {
     let active = download_state.active_downloads.lock().await;
     if let Some(download) = active.get(&task_id) {
         download
             .control_tx
-            .send(DownloadControl::Cancel)
+            .send(DownloadControl::Cancel {
+                keep_partial: keep_partial.unwrap_or(false),
+            })
             .map_err(|e| e.to_string())?;
         Ok(())
     } else {
@@ -381,12 +1705,196 @@ pub async fn cancel_download(
     }
 }
 
+// 在同一把锁内先确认所有任务都存在再逐个发送，任一 ID 无效时不执行任何操作；task_ids 为空表示全部任务
+async fn send_controls(
+    download_state: &DownloadState,
+    task_ids: Option<Vec<u64>>,
+    control: DownloadControl,
+) -> Result<Vec<u64>, String> {
+    let active = download_state.active_downloads.lock().await;
+
+    let task_ids = match task_ids {
+        Some(ids) => {
+            if let Some(missing) = ids.iter().find(|id| !active.contains_key(id)) {
+                return Err(format!("Download not found: {}", missing));
+            }
+            ids
+        }
+        None => active.keys().copied().collect(),
+    };
+
+    for task_id in &task_ids {
+        active[task_id]
+            .control_tx
+            .send(control.clone())
+            .map_err(|e| e.to_string())?;
+    }
+
+    Ok(task_ids)
+}
+
+#[tauri::command]
+pub async fn pause_downloads(
+    task_ids: Option<Vec<u64>>,
+    download_state: State<'_, DownloadState>,
+) -> Result<Vec<u64>, String> {
+    send_controls(download_state.inner(), task_ids, DownloadControl::Pause).await
+}
+
+#[tauri::command]
+pub async fn resume_downloads(
+    task_ids: Option<Vec<u64>>,
+    download_state: State<'_, DownloadState>,
+) -> Result<Vec<u64>, String> {
+    send_controls(download_state.inner(), task_ids, DownloadControl::Resume).await
+}
+
+#[tauri::command]
+pub async fn cancel_downloads(
+    task_ids: Option<Vec<u64>>,
+    keep_partial: Option<bool>,
+    download_state: State<'_, DownloadState>,
+) -> Result<Vec<u64>, String> {
+    let control = DownloadControl::Cancel {
+        keep_partial: keep_partial.unwrap_or(false),
+    };
+    send_controls(download_state.inner(), task_ids, control).await
+}
+
+// task_id 为空时设置全局限速并持久化，否则只调整该任务；limit 为空或 0 表示不限速
+#[tauri::command]
+pub async fn set_download_speed_limit(
+    app: AppHandle,
+    task_id: Option<u64>,
+    limit: Option<u64>,
+    download_state: State<'_, DownloadState>,
+) -> Result<(), String> {
+    let limit = limit.filter(|l| *l > 0);
+
+    match task_id {
+        Some(task_id) => {
+            let active = download_state.active_downloads.lock().await;
+            let download = active.get(&task_id).ok_or("Download not found")?;
+            download
+                .control_tx
+                .send(DownloadControl::SpeedLimit(limit))
+                .map_err(|e| e.to_string())
+        }
+        None => {
+            let mut settings = download_state.settings.lock().await;
+            settings.speed_limit = limit;
+            settings::save(&app, &settings)?;
+            download_state.speed_limiter.set_rate(limit);
+            Ok(())
+        }
+    }
+}
+
+#[tauri::command]
+pub async fn get_active_downloads(
+    download_state: State<'_, DownloadState>,
+) -> Result<Vec<DownloadSnapshot>, String> {
+    let active = download_state.active_downloads.lock().await;
+
+    let mut snapshots: Vec<DownloadSnapshot> = active
+        .values()
+        .filter_map(|d| d.snapshot.lock().ok().map(|s| s.clone()))
+        .collect();
+    snapshots.sort_by_key(|s| s.id);
+
+    Ok(snapshots)
+}
+
 #[tauri::command]
 pub async fn get_downloads(
     download_state: State<'_, DownloadState>,
 ) -> Result<Vec<DownloadRecord>, String> {
-    let records = download_state.records.lock().await;
-    Ok(records.clone())
+    download_state.store.list()
+}
+
+pub async fn reconcile_with_filesystem(
+    app: &AppHandle,
+    download_state: &DownloadState,
+) -> Result<ReconcileReport, String> {
+    // 除下载根目录外，还要检查按规则分入的各个子目录
+    let root = download_root(app, download_state).await?;
+    let mut dirs: Vec<PathBuf> = download_state
+        .store
+        .list()?
+        .iter()
+        .filter_map(|r| Path::new(&r.path).parent().map(Path::to_path_buf))
+        .collect();
+    dirs.push(root);
+    dirs.sort();
+    dirs.dedup();
+
+    // 已预留的文件名包含尚未开始写入的下载，它们的 .part 文件同样不能清理
+    let active_temp_paths = {
+        let reserved = download_state
+            .reserved_names
+            .lock()
+            .map_err(|e| e.to_string())?;
+        reserved.iter().map(|path| temp_path_for(path)).collect()
+    };
+    let store = download_state.store.clone();
+
+    tauri::async_runtime::spawn_blocking(move || {
+        reconcile::reconcile(&store, &dirs, &active_temp_paths)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+pub async fn reconcile_downloads(
+    app: AppHandle,
+    download_state: State<'_, DownloadState>,
+) -> Result<ReconcileReport, String> {
+    reconcile_with_filesystem(&app, download_state.inner()).await
+}
+
+#[tauri::command]
+pub async fn get_download_settings(
+    download_state: State<'_, DownloadState>,
+) -> Result<DownloadSettings, String> {
+    Ok(download_state.settings.lock().await.clone())
+}
+
+#[tauri::command]
+pub async fn set_download_settings(
+    app: AppHandle,
+    settings: DownloadSettings,
+    download_state: State<'_, DownloadState>,
+) -> Result<(), String> {
+    settings::validate_dir(&settings.download_root(&app)?)?;
+    download_state.http.reconfigure(&settings)?;
+    settings::save(&app, &settings)?;
+
+    download_state.speed_limiter.set_rate(settings.speed_limit);
+    *download_state.settings.lock().await = settings;
+    Ok(())
+}
+
+// zher 服务器要求登录时，前端把会话 Cookie 或 token 交给下载引擎，之后对该服务器的请求都会携带
+#[tauri::command]
+pub async fn set_server_credentials(
+    server: String,
+    token: Option<String>,
+    cookies: Option<Vec<String>>,
+    download_state: State<'_, DownloadState>,
+) -> Result<(), String> {
+    download_state
+        .http
+        .set_credentials(&server, token, &cookies.unwrap_or_default())
+}
+
+#[tauri::command]
+pub async fn get_download_dir(
+    app: AppHandle,
+    download_state: State<'_, DownloadState>,
+) -> Result<String, String> {
+    let root = download_root(&app, download_state.inner()).await?;
+    Ok(root.to_string_lossy().to_string())
 }
 
 #[tauri::command]
@@ -395,65 +1903,242 @@ pub async fn delete_download(
     id: String,
     download_state: State<'_, DownloadState>,
 ) -> Result<(), String> {
-    let (path, records) = {
-        let mut records = download_state.records.lock().await;
+    if let Some(record) = download_state.store.remove(&id)? {
+        let _ = tokio::fs::remove_file(&record.path).await;
+        remove_thumbnail(&app, &record.id);
+        unshare(&download_state, std::slice::from_ref(&record));
+    }
 
-        if let Some(pos) = records.iter().position(|r| r.id == id) {
-            let record = records.remove(pos);
-            let path = record.path.clone();
-            (Some(path), records.clone())
-        } else {
-            (None, records.clone())
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn delete_all_downloads(
+    app: AppHandle,
+    download_state: State<'_, DownloadState>,
+) -> Result<(), String> {
+    let records = download_state.store.clear()?;
+    unshare(&download_state, &records);
+
+    for record in records {
+        let _ = tokio::fs::remove_file(&record.path).await;
+        remove_thumbnail(&app, &record.id);
+    }
+
+    Ok(())
+}
+
+// 按 ID 列表和/或状态批量删除记录，在一个事务内完成；delete_files 默认为 true，同时删除文件和未完成的 .part
+#[tauri::command]
+pub async fn delete_downloads(
+    app: AppHandle,
+    ids: Option<Vec<String>>,
+    status: Option<DownloadStatus>,
+    delete_files: Option<bool>,
+    download_state: State<'_, DownloadState>,
+) -> Result<Vec<String>, String> {
+    let records = download_state
+        .store
+        .remove_matching(ids.as_deref(), status)?;
+
+    // 缩略图和分享只与记录有关，保留文件时也一并删除
+    for record in &records {
+        remove_thumbnail(&app, &record.id);
+    }
+    unshare(&download_state, &records);
+
+    if delete_files.unwrap_or(true) {
+        for record in &records {
+            let path = Path::new(&record.path);
+            let _ = tokio::fs::remove_file(path).await;
+            if record.status != DownloadStatus::Completed {
+                let _ = tokio::fs::remove_file(temp_path_for(path)).await;
+            }
         }
-    };
+    }
+
+    Ok(records.into_iter().map(|r| r.id).collect())
+}
 
-    if let Some(path) = path {
-        let _ = tokio::fs::remove_file(&path).await;
-        save_downloads(&app, &records).await;
+#[tauri::command]
+pub async fn extract_download(
+    app: AppHandle,
+    id: String,
+    download_state: State<'_, DownloadState>,
+) -> Result<Vec<DownloadRecord>, String> {
+    let record = download_state
+        .store
+        .get(&id)?
+        .ok_or("Download not found")?;
+    if record.status != DownloadStatus::Completed {
+        return Err("Download is not completed".to_string());
     }
 
+    let download_state = download_state.inner().clone();
+    tauri::async_runtime::spawn_blocking(move || extract_archive(&app, &download_state, &record))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+// 元数据写入 record，由调用方保存
+fn generate_media(app: &AppHandle, record: &mut DownloadRecord) -> Result<(), String> {
+    let path = Path::new(&record.path);
+    let mime_type = record
+        .mime_type
+        .clone()
+        .unwrap_or_else(|| mime::resolve(path, None));
+    let thumbnail = media::thumbnail_path(app, &record.id)?;
+    record.media = Some(media::generate(path, &mime_type, &thumbnail)?);
     Ok(())
 }
 
+// 已删除的记录不再响应接收方的上传请求
+fn unshare(download_state: &DownloadState, records: &[DownloadRecord]) {
+    if let Ok(mut shared) = download_state.shared_files.lock() {
+        shared.retain(|_, file| !records.iter().any(|r| r.id == file.record_id));
+    }
+}
+
+fn remove_thumbnail(app: &AppHandle, id: &str) {
+    if let Ok(thumbnail) = media::thumbnail_path(app, id) {
+        let _ = std::fs::remove_file(thumbnail);
+    }
+}
+
+// 返回 JPEG 数据；旧记录或缓存被系统清理时现场生成，已确认无法生成的不再重试
 #[tauri::command]
-pub async fn delete_all_downloads(
+pub async fn get_download_thumbnail(
     app: AppHandle,
+    id: String,
+    download_state: State<'_, DownloadState>,
+) -> Result<tauri::ipc::Response, String> {
+    // 先确认是已有的记录，再用它的 ID 拼接缓存路径
+    let mut record = download_state
+        .store
+        .get(&id)?
+        .ok_or("Download not found")?;
+    let thumbnail = media::thumbnail_path(&app, &record.id)?;
+
+    if !thumbnail.is_file() {
+        if record.status != DownloadStatus::Completed {
+            return Err("Download is not completed".to_string());
+        }
+        if record.media.as_ref().is_some_and(|m| !m.thumbnail) {
+            return Err("No thumbnail".to_string());
+        }
+
+        // 只写回 media，生成期间记录可能已被改名
+        let download_state = download_state.inner().clone();
+        tauri::async_runtime::spawn_blocking(move || {
+            generate_media(&app, &mut record)?;
+            let media = record.media.take();
+            download_state.store.update(&record.id, |r| {
+                r.media = media;
+                Ok(())
+            })
+        })
+        .await
+        .map_err(|e| e.to_string())??;
+
+        if !thumbnail.is_file() {
+            return Err("No thumbnail".to_string());
+        }
+    }
+
+    let bytes = tokio::fs::read(&thumbnail)
+        .await
+        .map_err(|e| format!("Failed to read thumbnail: {}", e))?;
+    Ok(tauri::ipc::Response::new(bytes))
+}
+
+// 优先使用下载完成时记录的类型，没有记录的文件（例如旧记录）现场判断
+fn mime_for_path(download_state: &DownloadState, path: &str) -> String {
+    download_state
+        .store
+        .find_by_path(path)
+        .ok()
+        .flatten()
+        .and_then(|record| record.mime_type)
+        .filter(|m| mime::is_specific(m))
+        .unwrap_or_else(|| mime::resolve(Path::new(path), None))
+}
+
+// 可以打开某类文件的应用，id 用于 open_with_download 指定应用；Android 由系统选择界面处理
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(target_os = "android", allow(dead_code))]
+pub struct OpenWithApp {
+    pub id: String,
+    pub name: String,
+    pub is_default: bool,
+}
+
+// Android 上类型未知时让系统列出所有应用，octet-stream 几乎没有应用会响应
+#[cfg(target_os = "android")]
+fn intent_mime(download_state: &DownloadState, path: &str) -> String {
+    let mime_type = mime_for_path(download_state, path);
+    if mime::is_specific(&mime_type) {
+        mime_type
+    } else {
+        "*/*".to_string()
+    }
+}
+
+#[tauri::command]
+pub async fn open_download_file(
+    app: AppHandle,
+    path: String,
     download_state: State<'_, DownloadState>,
 ) -> Result<(), String> {
-    let paths = {
-        let mut records = download_state.records.lock().await;
-        let paths: Vec<String> = records.iter().map(|r| r.path.clone()).collect();
-        records.clear();
-        paths
-    };
+    #[cfg(target_os = "android")]
+    {
+        let mime_type = intent_mime(&download_state, &path);
+        open_file_android(&app, &path, &mime_type, false).await
+    }
 
-    for path in paths {
-        let _ = tokio::fs::remove_file(&path).await;
+    #[cfg(not(target_os = "android"))]
+    {
+        let _ = download_state;
+        launcher::open(&app, &path)
     }
+}
 
-    save_downloads(&app, &[]).await;
+#[tauri::command]
+pub async fn reveal_in_folder(app: AppHandle, path: String) -> Result<(), String> {
+    #[cfg(target_os = "android")]
+    {
+        let _ = (app, path);
+        Err("Not supported on this platform".to_string())
+    }
 
-    Ok(())
+    #[cfg(not(target_os = "android"))]
+    {
+        launcher::reveal(&app, &path)
+    }
 }
 
+// 按文件类型列出系统关联的应用，列表为空时前端直接调用 open_with_download 使用系统选择界面
 #[tauri::command]
-pub async fn open_download_file(app: AppHandle, path: String) -> Result<(), String> {
+pub async fn get_open_with_apps(
+    path: String,
+    download_state: State<'_, DownloadState>,
+) -> Result<Vec<OpenWithApp>, String> {
     #[cfg(target_os = "android")]
     {
-        open_file_android(&app, &path).await
+        let _ = (path, download_state);
+        Ok(Vec::new())
     }
 
     #[cfg(not(target_os = "android"))]
     {
-        use tauri_plugin_opener::OpenerExt;
-        app.opener().open_url(&path, None::<&str>).unwrap();
-        Ok(())
+        let mime_type = mime_for_path(&download_state, &path);
+        tauri::async_runtime::spawn_blocking(move || launcher::list_apps(&path, &mime_type))
+            .await
+            .map_err(|e| e.to_string())
     }
 }
 
 #[tauri::command]
 pub async fn rename_download(
-    app: AppHandle,
     id: String,
     new_name: String,
     download_state: State<'_, DownloadState>,
@@ -461,62 +2146,142 @@ pub async fn rename_download(
     if new_name.trim().is_empty() {
         return Err("Invalid filename".to_string());
     }
+    let new_name = filename::sanitize_filename(&new_name);
 
-    let (old_path, new_path, records) = {
-        let mut records = download_state.records.lock().await;
-
-        if let Some(record) = records.iter_mut().find(|r| r.id == id) {
-            let old_path = PathBuf::from(&record.path);
-            let parent = old_path.parent().ok_or("Invalid path")?;
-            let new_path = parent.join(&new_name);
-
-            if new_path.exists() {
-                return Err("File already exists".to_string());
-            }
+    // 与 reserve_filename 保持相同的加锁顺序：先预留表，再数据库
+    let reserved = download_state
+        .reserved_names
+        .lock()
+        .map_err(|e| e.to_string())?;
 
-            let old_path_str = record.path.clone();
-            record.filename = new_name.clone();
-            record.path = new_path.to_string_lossy().to_string();
+    // 文件重命名失败时事务回滚，记录保持不变
+    download_state.store.update(&id, |record| {
+        let old_path = PathBuf::from(&record.path);
+        let parent = old_path.parent().ok_or("Invalid path")?;
+        let new_path = parent.join(&new_name);
 
-            (
-                old_path_str,
-                new_path.to_string_lossy().to_string(),
-                records.clone(),
-            )
-        } else {
-            return Err("Download not found".to_string());
+        if new_path.exists() || reserved.contains(&new_path) {
+            return Err("File already exists".to_string());
         }
-    };
-
-    tokio::fs::rename(&old_path, &new_path)
-        .await
-        .map_err(|e| format!("Failed to rename file: {}", e))?;
 
-    save_downloads(&app, &records).await;
+        std::fs::rename(&old_path, &new_path)
+            .map_err(|e| format!("Failed to rename file: {}", e))?;
 
-    Ok(())
+        record.filename = new_name.clone();
+        record.path = new_path.to_string_lossy().to_string();
+        Ok(())
+    })
 }
 
+// 把已完成的下载发布到 zher 服务器，返回的 file-meta 由前端通过该服务器的连接发出
 #[tauri::command]
 pub async fn share_download(
+    id: String,
+    server: String,
+    download_state: State<'_, DownloadState>,
+) -> Result<share::ShareOffer, String> {
+    let record = download_state
+        .store
+        .get(&id)?
+        .ok_or("Download not found")?;
+    if record.status != DownloadStatus::Completed {
+        return Err("Download is not completed".to_string());
+    }
+    let server = server_origin(&server).ok_or("Invalid server URL")?;
+
+    let file_id = format!("share-{}", download_state.next_task_id());
+    let mime_type = mime_for_path(&download_state, &record.path);
+    let path = PathBuf::from(&record.path);
+    let offer = share::offer(file_id.clone(), &path, &record.filename, mime_type)?;
+
+    download_state
+        .shared_files
+        .lock()
+        .map_err(|e| e.to_string())?
+        .insert(
+            file_id,
+            share::SharedFile {
+                record_id: record.id,
+                path,
+                server,
+            },
+        );
+
+    Ok(offer)
+}
+
+// 服务器发出 start-upload 后由前端调用，每个接收方各上传一次
+#[tauri::command]
+pub async fn upload_shared_file(
     app: AppHandle,
-    path: String,
+    file_id: String,
+    transfer_id: String,
+    offset: Option<u64>,
+    end: Option<u64>,
+    download_state: State<'_, DownloadState>,
 ) -> Result<(), String> {
-    open_download_file(app, path).await
+    let mut shared = download_state
+        .shared_files
+        .lock()
+        .map_err(|e| e.to_string())?
+        .get(&file_id)
+        .cloned()
+        .ok_or("Unknown shared file")?;
+
+    // 分享后文件可能被改名，按记录取当前路径
+    let store = download_state.store.clone();
+    let record_id = shared.record_id.clone();
+    let record = blocking(move || store.get(&record_id))
+        .await?
+        .ok_or("Download not found")?;
+    shared.path = PathBuf::from(record.path);
+
+    let result = share::upload(
+        &app,
+        &download_state.http,
+        download_state.speed_limiter.clone(),
+        &shared,
+        &file_id,
+        &transfer_id,
+        offset.unwrap_or(0),
+        end,
+    )
+    .await;
+
+    match &result {
+        Ok(size) => app.emit(
+            "share-completed",
+            serde_json::json!({ "fileId": file_id, "transferId": transfer_id, "size": size }),
+        ),
+        Err(e) => app.emit(
+            "share-failed",
+            serde_json::json!({ "fileId": file_id, "transferId": transfer_id, "error": e }),
+        ),
+    }
+    .unwrap_or(());
+
+    result.map(|_| ())
 }
+
 #[cfg(target_os = "android")]
-async fn open_file_android(app: &AppHandle, path: &str) -> Result<(), String> {
+async fn open_file_android(
+    app: &AppHandle,
+    path: &str,
+    mime_type: &str,
+    chooser: bool,
+) -> Result<(), String> {
     // Key points for opening files on Android:
     // 1. Use Activity's ClassLoader to load AndroidX classes in Multidex environment
     // 2. Convert file:// path to content:// URI using FileProvider for security (Android 7.0+)
-    // 3. Set proper MIME type based on file extension
+    // 3. Set the MIME type resolved by the mime module (content, server type, extension)
     // 4. Grant FLAG_GRANT_READ_URI_PERMISSION to allow target app to read the file
-    // 5. Use Intent.createChooser to let user select which app to open the file with
+    // 5. Use Intent.createChooser to let user select which app to open the file with (open with only)
     
     use jni::objects::{JObject, JValue};
     use jni::JavaVM;
 
     let path_owned = path.to_string();
+    let mime_owned = mime_type.to_string();
     let app_identifier = app.config().identifier.clone();
 
     app.run_on_main_thread(move || unsafe {
@@ -578,25 +2343,7 @@ async fn open_file_android(app: &AppHandle, path: &str) -> Result<(), String> {
             .l()
             .unwrap();
 
-        let extension = std::path::Path::new(&path_owned)
-            .extension()
-            .and_then(|e| e.to_str())
-            .unwrap_or("");
-
-        let mime_type = match extension.to_lowercase().as_str() {
-            "pdf" => "application/pdf",
-            "jpg" | "jpeg" => "image/jpeg",
-            "png" => "image/png",
-            "gif" => "image/gif",
-            "mp4" => "video/mp4",
-            "mp3" => "audio/mpeg",
-            "txt" => "text/plain",
-            "zip" => "application/zip",
-            "apk" => "application/vnd.android.package-archive",
-            _ => "*/*",
-        };
-
-        let mime_str = env.new_string(mime_type).unwrap();
+        let mime_str = env.new_string(&mime_owned).unwrap();
 
         let intent_class = env.find_class("android/content/Intent").unwrap();
         let action_view = env
@@ -630,9 +2377,10 @@ async fn open_file_android(app: &AppHandle, path: &str) -> Result<(), String> {
         )
         .unwrap();
 
-        let chooser_title = env.new_string("Open with").unwrap();
-        let chooser = env
-            .call_static_method(
+        // 不要求选择时交给系统，已设置默认应用的类型会直接打开
+        let target = if chooser {
+            let chooser_title = env.new_string("Open with").unwrap();
+            env.call_static_method(
                 &intent_class,
                 "createChooser",
                 "(Landroid/content/Intent;Ljava/lang/CharSequence;)Landroid/content/Intent;",
@@ -640,13 +2388,16 @@ async fn open_file_android(app: &AppHandle, path: &str) -> Result<(), String> {
             )
             .unwrap()
             .l()
-            .unwrap();
+            .unwrap()
+        } else {
+            intent
+        };
 
         env.call_method(
             &activity,
             "startActivity",
             "(Landroid/content/Intent;)V",
-            &[JValue::Object(&chooser)],
+            &[JValue::Object(&target)],
         )
         .unwrap();
     })
@@ -655,29 +2406,24 @@ async fn open_file_android(app: &AppHandle, path: &str) -> Result<(), String> {
     Ok(())
 }
 
+// 与 open_download_file 不同，总是让用户选择应用，或使用 get_open_with_apps 返回的指定应用
 #[tauri::command]
-pub async fn open_with_download(app: AppHandle, path: String) -> Result<(), String> {
-    open_download_file(app, path).await
-}
-
-async fn save_downloads(app: &AppHandle, records: &[DownloadRecord]) {
-    if let Ok(app_data) = app.path().app_data_dir() {
-        let _ = tokio::fs::create_dir_all(&app_data).await;
-        let downloads_file = app_data.join("downloads.json");
-        if let Ok(json) = serde_json::to_string(records) {
-            let _ = tokio::fs::write(downloads_file, json).await;
-        }
+pub async fn open_with_download(
+    app: AppHandle,
+    path: String,
+    application: Option<String>,
+    download_state: State<'_, DownloadState>,
+) -> Result<(), String> {
+    #[cfg(target_os = "android")]
+    {
+        let _ = application;
+        let mime_type = intent_mime(&download_state, &path);
+        open_file_android(&app, &path, &mime_type, true).await
     }
-}
 
-pub async fn load_downloads(app: &AppHandle) -> Vec<DownloadRecord> {
-    if let Ok(app_data) = app.path().app_data_dir() {
-        let downloads_file = app_data.join("downloads.json");
-        if let Ok(content) = tokio::fs::read_to_string(downloads_file).await {
-            if let Ok(records) = serde_json::from_str(&content) {
-                return records;
-            }
-        }
+    #[cfg(not(target_os = "android"))]
+    {
+        let _ = download_state;
+        launcher::open_with(&app, &path, application.as_deref())
     }
-    Vec::new()
 }
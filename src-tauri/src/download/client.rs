@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use reqwest::cookie::Jar;
+
+use super::{server_origin, DownloadSettings};
+
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+const TCP_KEEPALIVE: Duration = Duration::from_secs(30);
+
+// 所有下载共享的 HTTP 客户端，复用连接池，并按服务器附加会话凭据
+// 不启用 gzip：压缩传输会让 Range 偏移和 Content-Length 与文件实际大小不一致，无法续传
+#[derive(Clone)]
+pub struct HttpClient {
+    client: Arc<RwLock<reqwest::Client>>,
+    cookies: Arc<Jar>,
+    // 服务器 origin -> Bearer token
+    tokens: Arc<RwLock<HashMap<String, String>>>,
+}
+
+impl HttpClient {
+    pub fn new(settings: &DownloadSettings) -> Result<Self, String> {
+        let cookies = Arc::new(Jar::default());
+        let client = build_client(settings, &cookies)?;
+
+        Ok(Self {
+            client: Arc::new(RwLock::new(client)),
+            cookies,
+            tokens: Default::default(),
+        })
+    }
+
+    // 设置变化后重建客户端，Cookie 和 token 保持不变
+    pub fn reconfigure(&self, settings: &DownloadSettings) -> Result<(), String> {
+        let client = build_client(settings, &self.cookies)?;
+        *self.client.write().map_err(|e| e.to_string())? = client;
+        Ok(())
+    }
+
+    pub fn get(&self, url: &str) -> reqwest::RequestBuilder {
+        self.request(reqwest::Method::GET, url)
+    }
+
+    pub fn request(&self, method: reqwest::Method, url: &str) -> reqwest::RequestBuilder {
+        let client = self
+            .client
+            .read()
+            .map(|c| c.clone())
+            .unwrap_or_default();
+        let mut request = client.request(method, url);
+
+        let token = server_origin(url).and_then(|origin| {
+            self.tokens
+                .read()
+                .ok()
+                .and_then(|tokens| tokens.get(&origin).cloned())
+        });
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+
+        request
+    }
+
+    // 服务器要求登录时由前端传入会话 Cookie 或 token；token 为空表示清除
+    pub fn set_credentials(
+        &self,
+        server: &str,
+        token: Option<String>,
+        cookies: &[String],
+    ) -> Result<(), String> {
+        let url = url::Url::parse(server).map_err(|e| format!("Invalid server URL: {}", e))?;
+        let origin = server_origin(server).ok_or("Invalid server URL")?;
+
+        for cookie in cookies {
+            self.cookies.add_cookie_str(cookie, &url);
+        }
+
+        let mut tokens = self.tokens.write().map_err(|e| e.to_string())?;
+        match token.filter(|t| !t.trim().is_empty()) {
+            Some(token) => tokens.insert(origin, token),
+            None => tokens.remove(&origin),
+        };
+
+        Ok(())
+    }
+}
+
+// 如实标识客户端，例如 "zher/0.1.0 (android; aarch64)"
+fn user_agent() -> String {
+    format!(
+        "zher/{} ({}; {})",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    )
+}
+
+fn build_client(settings: &DownloadSettings, cookies: &Arc<Jar>) -> Result<reqwest::Client, String> {
+    let mut headers = reqwest::header::HeaderMap::new();
+    if let Some(client_id) = settings.client_id.as_deref().filter(|s| !s.trim().is_empty()) {
+        let value = reqwest::header::HeaderValue::from_str(client_id.trim())
+            .map_err(|e| format!("Invalid client id: {}", e))?;
+        headers.insert("X-Zher-Client", value);
+    }
+
+    reqwest::Client::builder()
+        .user_agent(user_agent())
+        .default_headers(headers)
+        .cookie_provider(Arc::clone(cookies))
+        .connect_timeout(settings.connect_timeout())
+        .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+        .tcp_keepalive(TCP_KEEPALIVE)
+        .build()
+        .map_err(|e| e.to_string())
+}
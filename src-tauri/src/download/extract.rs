@@ -0,0 +1,268 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use super::filename::sanitize_filename;
+use super::settings::MIN_FREE_SPACE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveKind {
+    // 按扩展名判断，并要求内容与之相符；docx、apk 等也是 zip 格式，不能只看内容
+    pub fn detect(filename: &str, mime: &str) -> Option<Self> {
+        let lower = filename.to_lowercase();
+        if lower.ends_with(".tar.gz") || lower.ends_with(".tgz") {
+            return (mime == "application/gzip").then_some(Self::TarGz);
+        }
+
+        match Path::new(&lower).extension().and_then(|e| e.to_str()) {
+            Some("zip") => (mime == "application/zip").then_some(Self::Zip),
+            Some("tar") => Some(Self::Tar),
+            _ => None,
+        }
+    }
+
+    fn suffix_len(self, filename: &str) -> usize {
+        let lower = filename.to_lowercase();
+        [".tar.gz", ".tgz", ".zip", ".tar"]
+            .iter()
+            .find(|suffix| lower.ends_with(*suffix))
+            .map_or(0, |suffix| suffix.len())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExtractedFile {
+    pub path: PathBuf,
+    // 相对于解压目录的路径，用于进度显示
+    pub name: String,
+    pub size: u64,
+}
+
+// 在压缩包所在目录创建以压缩包命名的文件夹，已存在时加序号
+pub fn create_target_dir(archive: &Path, kind: ArchiveKind) -> Result<PathBuf, String> {
+    let parent = archive.parent().ok_or("Invalid path")?;
+    let filename = archive
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .ok_or("Invalid path")?;
+    let stem = sanitize_filename(&filename[..filename.len() - kind.suffix_len(&filename)]);
+
+    for index in 0..1000 {
+        let name = if index == 0 {
+            stem.clone()
+        } else {
+            format!("{} ({})", stem, index)
+        };
+        let dir = parent.join(name);
+        match std::fs::create_dir(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(format!("Failed to create folder: {}", e)),
+        }
+    }
+
+    Err("Failed to create folder".to_string())
+}
+
+// 每解压一个文件调用一次 on_entry(序号, 总数, 文件)
+pub fn extract(
+    archive: &Path,
+    kind: ArchiveKind,
+    target: &Path,
+    mut on_entry: impl FnMut(usize, Option<usize>, &ExtractedFile),
+) -> Result<Vec<ExtractedFile>, String> {
+    let file = File::open(archive).map_err(|e| format!("Failed to open archive: {}", e))?;
+    let mut extracted = Vec::new();
+
+    match kind {
+        ArchiveKind::Zip => {
+            let mut zip = zip::ZipArchive::new(file).map_err(|e| format!("Invalid zip: {}", e))?;
+            let total = zip.len();
+            for index in 0..total {
+                let mut entry = zip.by_index(index).map_err(|e| format!("Invalid zip: {}", e))?;
+                if entry.is_dir() || entry.is_symlink() {
+                    continue;
+                }
+                let name = entry.name().to_string();
+                let size = entry.size();
+                if let Some(file) = write_entry(target, &name, size, &mut entry)? {
+                    on_entry(index + 1, Some(total), &file);
+                    extracted.push(file);
+                }
+            }
+        }
+        ArchiveKind::Tar => extract_tar(tar::Archive::new(file), target, &mut extracted, &mut on_entry)?,
+        ArchiveKind::TarGz => extract_tar(
+            tar::Archive::new(flate2::read::GzDecoder::new(file)),
+            target,
+            &mut extracted,
+            &mut on_entry,
+        )?,
+    }
+
+    Ok(extracted)
+}
+
+// tar 只能顺序读取，无法预先知道条目总数
+fn extract_tar<R: Read>(
+    mut archive: tar::Archive<R>,
+    target: &Path,
+    extracted: &mut Vec<ExtractedFile>,
+    on_entry: &mut impl FnMut(usize, Option<usize>, &ExtractedFile),
+) -> Result<(), String> {
+    let entries = archive.entries().map_err(|e| format!("Invalid tar: {}", e))?;
+    for (index, entry) in entries.enumerate() {
+        let mut entry = entry.map_err(|e| format!("Invalid tar: {}", e))?;
+        // 链接、设备文件等一律跳过，只解压普通文件
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = String::from_utf8_lossy(&entry.path_bytes()).to_string();
+        let size = entry.size();
+        if let Some(file) = write_entry(target, &name, size, &mut entry)? {
+            on_entry(index + 1, None, &file);
+            extracted.push(file);
+        }
+    }
+    Ok(())
+}
+
+// 防止 zip slip：拒绝含 .. 的路径，绝对路径和盘符都当作相对路径的一部分处理，每一级都经过文件名清洗
+fn safe_relative_path(name: &str) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    for part in name.split(['/', '\\']) {
+        match part {
+            "" | "." => continue,
+            ".." => return None,
+            part => path.push(sanitize_filename(part)),
+        }
+    }
+    (path.components().next().is_some()).then_some(path)
+}
+
+fn write_entry(
+    target: &Path,
+    name: &str,
+    size: u64,
+    reader: &mut impl Read,
+) -> Result<Option<ExtractedFile>, String> {
+    let Some(relative) = safe_relative_path(name) else {
+        log::warn!("Skipped unsafe archive entry: {}", name);
+        return Ok(None);
+    };
+    let path = target.join(&relative);
+
+    let available = fs4::available_space(target)
+        .map_err(|e| format!("Failed to query free space: {}", e))?;
+    if available < size.saturating_add(MIN_FREE_SPACE) {
+        return Err("Insufficient storage".to_string());
+    }
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create folder: {}", e))?;
+    }
+
+    // 同名条目只保留第一个
+    let mut out = match OpenOptions::new().write(true).create_new(true).open(&path) {
+        Ok(out) => out,
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            log::warn!("Skipped duplicate archive entry: {}", name);
+            return Ok(None);
+        }
+        Err(e) => return Err(format!("Failed to create file: {}", e)),
+    };
+
+    // 按声明的大小截断，防止条目实际内容远大于头部声明
+    let written = io::copy(&mut reader.take(size), &mut out)
+        .map_err(|e| format!("Failed to extract {}: {}", name, e))?;
+
+    Ok(Some(ExtractedFile {
+        path,
+        name: relative.to_string_lossy().to_string(),
+        size: written,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn relative(name: &str) -> Option<String> {
+        safe_relative_path(name).map(|p| {
+            p.components()
+                .map(|c| c.as_os_str().to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+                .join("/")
+        })
+    }
+
+    fn temp_target(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("zher-extract-test-{}", std::process::id()))
+            .join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn rejects_parent_components() {
+        assert_eq!(relative("../x"), None);
+        assert_eq!(relative("a/../../x"), None);
+        assert_eq!(relative("a\\..\\x"), None);
+        assert_eq!(relative("a/b/.."), None);
+    }
+
+    #[test]
+    fn absolute_paths_stay_inside_target() {
+        assert_eq!(relative("/etc/passwd").as_deref(), Some("etc/passwd"));
+        assert_eq!(relative("C:\\x").as_deref(), Some("C_/x"));
+        assert_eq!(relative("\\\\server\\share\\x").as_deref(), Some("server/share/x"));
+    }
+
+    #[test]
+    fn accepts_mixed_separators() {
+        assert_eq!(relative("a\\b/c.txt").as_deref(), Some("a/b/c.txt"));
+        assert_eq!(relative("./a//b\\.\\c").as_deref(), Some("a/b/c"));
+    }
+
+    #[test]
+    fn rejects_names_without_components() {
+        assert_eq!(relative(""), None);
+        assert_eq!(relative("."), None);
+        assert_eq!(relative("./././"), None);
+        assert_eq!(relative("//\\\\"), None);
+    }
+
+    #[test]
+    fn write_entry_skips_unsafe_names() {
+        let target = temp_target("unsafe");
+        let written = write_entry(&target, "../escaped.txt", 4, &mut &b"data"[..]).unwrap();
+        assert!(written.is_none());
+        assert!(!target.parent().unwrap().join("escaped.txt").exists());
+    }
+
+    #[test]
+    fn write_entry_places_absolute_names_under_target() {
+        let target = temp_target("absolute");
+        let file = write_entry(&target, "/etc/passwd", 4, &mut &b"data"[..])
+            .unwrap()
+            .unwrap();
+        assert_eq!(file.path, target.join("etc").join("passwd"));
+        assert_eq!(std::fs::read(&file.path).unwrap(), b"data");
+    }
+
+    #[test]
+    fn write_entry_truncates_to_declared_size() {
+        let target = temp_target("truncate");
+        let file = write_entry(&target, "a.txt", 2, &mut &b"data"[..]).unwrap().unwrap();
+        assert_eq!(file.size, 2);
+        assert_eq!(std::fs::read(&file.path).unwrap(), b"da");
+    }
+}
@@ -0,0 +1,531 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use unicode_normalization::UnicodeNormalization;
+
+use super::{mime, temp_path_for, DownloadStore};
+
+// 为 "(n)" 后缀和 ".part" 留出余量，常见文件系统单个文件名上限为 255 字节
+const MAX_FILENAME_BYTES: usize = 200;
+const MAX_EXTENSION_BYTES: usize = 16;
+const FALLBACK_FILENAME: &str = "download";
+
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    #[default]
+    Rename,
+    Overwrite,
+    Skip,
+    Ask,
+}
+
+// 服务器或用户提供的文件名只能作为单个路径组件使用，不能包含分隔符或逃出下载目录
+pub fn sanitize_filename(name: &str) -> String {
+    let cleaned: String = name
+        .nfc()
+        .filter(|c| !c.is_control() && !is_bidi_control(*c))
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c => c,
+        })
+        .collect();
+
+    let trimmed = cleaned
+        .trim_start_matches(|c: char| c == '.' || c.is_whitespace())
+        .trim_end_matches(|c: char| c == '.' || c.is_whitespace());
+
+    if trimmed.is_empty() {
+        return FALLBACK_FILENAME.to_string();
+    }
+
+    let device_name = trimmed.split('.').next().unwrap_or("").trim_end();
+    let name = if RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(device_name))
+    {
+        format!("_{}", trimmed)
+    } else {
+        trimmed.to_string()
+    };
+
+    truncate_filename(&name, MAX_FILENAME_BYTES)
+}
+
+fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{200E}' | '\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+// 按字节截断，尽量保留扩展名，且不在多字节字符中间截断
+fn truncate_filename(name: &str, max_bytes: usize) -> String {
+    if name.len() <= max_bytes {
+        return name.to_string();
+    }
+
+    let (stem, ext) = match name.rfind('.') {
+        Some(pos) if pos > 0 && name.len() - pos <= MAX_EXTENSION_BYTES + 1 => name.split_at(pos),
+        _ => (name, ""),
+    };
+
+    let budget = max_bytes - ext.len();
+    let mut end = budget.min(stem.len());
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    format!("{}{}", stem[..end].trim_end(), ext)
+}
+
+// 解析 Content-Disposition 中的文件名，优先使用 RFC 5987 的 filename*（中文文件名通常用它传递）
+pub fn parse_content_disposition(header: &[u8]) -> Option<String> {
+    let header = String::from_utf8_lossy(header);
+    let mut plain = None;
+
+    for param in split_params(&header).into_iter().skip(1) {
+        let Some((key, value)) = param.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_ascii_lowercase();
+        let value = value.trim();
+
+        if key == "filename*" {
+            if let Some(name) = decode_ext_value(value) {
+                return Some(name).filter(|n| !n.trim().is_empty());
+            }
+        } else if key == "filename" {
+            plain = Some(unquote(value));
+        }
+    }
+
+    // 部分服务器在普通 filename 中直接放百分号编码的 UTF-8
+    plain
+        .map(|name| {
+            percent_decode(&name)
+                .and_then(|bytes| String::from_utf8(bytes).ok())
+                .unwrap_or(name)
+        })
+        .filter(|n| !n.trim().is_empty())
+}
+
+// 按分号拆分参数，忽略引号内的分号
+fn split_params(header: &str) -> Vec<String> {
+    let mut params = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut escaped = false;
+
+    for c in header.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            ';' if !in_quotes => {
+                params.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    params.push(current);
+    params
+}
+
+fn unquote(value: &str) -> String {
+    let Some(inner) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) else {
+        return value.to_string();
+    };
+
+    let mut result = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(next) = chars.next() {
+                result.push(next);
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+// charset'language'percent-encoded
+fn decode_ext_value(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '\'');
+    let charset = parts.next()?.trim().to_ascii_lowercase();
+    let _language = parts.next()?;
+    let encoded = parts.next()?;
+    let bytes = percent_decode(encoded).unwrap_or_else(|| encoded.as_bytes().to_vec());
+
+    match charset.as_str() {
+        "utf-8" | "utf8" | "" => String::from_utf8(bytes).ok(),
+        "iso-8859-1" | "latin1" => Some(bytes.into_iter().map(char::from).collect()),
+        _ => None,
+    }
+}
+
+// 不含 % 或编码无效时返回 None
+fn percent_decode(value: &str) -> Option<Vec<u8>> {
+    if !value.contains('%') {
+        return None;
+    }
+
+    let bytes = value.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3)?;
+            let hex = std::str::from_utf8(hex).ok()?;
+            result.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            result.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Some(result)
+}
+
+// 下载完成后确定最终文件名：调用方没有给出文件名时采用服务器提供的名字；
+// 缺少扩展名时按内容特征或服务器声明的类型补全，扩展名与强内容特征明显不符时替换
+pub fn resolve_final_name(
+    current: &str,
+    derive_from_server: bool,
+    disposition_name: Option<&str>,
+    sniffed: Option<&str>,
+    declared: Option<&str>,
+) -> String {
+    let name = match disposition_name {
+        Some(server_name) if derive_from_server => sanitize_filename(server_name),
+        _ => current.to_string(),
+    };
+
+    let path = Path::new(&name);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or(&name);
+    let guessed = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .and_then(|ext| mime_guess::from_ext(ext).first());
+
+    let fixed = match guessed {
+        // 没有扩展名或扩展名未知时追加，例如 report.v2 -> report.v2.pdf
+        None => sniffed
+            .or(declared.filter(|m| mime::is_specific(m)))
+            .and_then(mime::extension_for)
+            .map(|ext| format!("{}.{}", name, ext)),
+        // 服务器声明的类型经常不准，只有强特征属于不同大类（例如 .txt 实际是 PDF）时才替换
+        Some(guessed) => sniffed
+            .filter(|sniffed| mime::is_distinctive(sniffed))
+            .filter(|sniffed| sniffed.split('/').next() != Some(guessed.type_().as_str()))
+            .and_then(mime::extension_for)
+            .map(|ext| format!("{}.{}", stem, ext)),
+    };
+
+    fixed.map(|n| sanitize_filename(&n)).unwrap_or(name)
+}
+
+pub type ReservedNames = Arc<Mutex<HashSet<PathBuf>>>;
+
+// 持有期间目标路径不会被其他下载选中，drop 时释放；未写入任何数据的 .part 占位文件一并删除
+pub struct FilenameReservation {
+    pub filename: String,
+    pub file_path: PathBuf,
+    pub temp_path: PathBuf,
+    reserved: ReservedNames,
+}
+
+impl Drop for FilenameReservation {
+    fn drop(&mut self) {
+        if let Ok(mut reserved) = self.reserved.lock() {
+            reserved.remove(&self.file_path);
+        }
+
+        if std::fs::metadata(&self.temp_path).map(|m| m.len() == 0).unwrap_or(false) {
+            let _ = std::fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+pub enum Reservation {
+    Reserved(FilenameReservation),
+    Conflict { suggested: String },
+}
+
+pub fn reserve_filename(
+    reserved: &ReservedNames,
+    dir: &Path,
+    filename: &str,
+    store: &DownloadStore,
+    policy: ConflictPolicy,
+) -> Result<Reservation, String> {
+    let mut names = reserved.lock().map_err(|e| e.to_string())?;
+
+    let is_name_taken = |names: &HashSet<PathBuf>, name: &str| -> bool {
+        let path = dir.join(name);
+        names.contains(&path)
+            || store.is_filename_taken(name).unwrap_or(false)
+            || path.exists()
+            || temp_path_for(&path).exists()
+    };
+
+    let filename = match policy {
+        ConflictPolicy::Overwrite => {
+            if names.contains(&dir.join(filename)) {
+                return Err("File is already being downloaded".to_string());
+            }
+
+            let file_path = dir.join(filename);
+            let temp_path = temp_path_for(&file_path);
+            std::fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&temp_path)
+                .map_err(|e| format!("Failed to create file: {}", e))?;
+
+            names.insert(file_path.clone());
+            return Ok(Reservation::Reserved(FilenameReservation {
+                filename: filename.to_string(),
+                file_path,
+                temp_path,
+                reserved: Arc::clone(reserved),
+            }));
+        }
+        ConflictPolicy::Skip | ConflictPolicy::Ask if is_name_taken(&names, filename) => {
+            let suggested = next_free_name(filename, |name| is_name_taken(&names, name));
+            return Ok(Reservation::Conflict { suggested });
+        }
+        _ => filename.to_string(),
+    };
+
+    let mut candidate = filename.clone();
+    loop {
+        if is_name_taken(&names, &candidate) {
+            candidate = next_free_name(&filename, |name| is_name_taken(&names, name));
+        }
+
+        let file_path = dir.join(&candidate);
+        let temp_path = temp_path_for(&file_path);
+
+        // create_new 保证即使其他进程同时写入该目录也不会共用同一个 .part
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)
+        {
+            Ok(_) => {
+                names.insert(file_path.clone());
+                return Ok(Reservation::Reserved(FilenameReservation {
+                    filename: candidate,
+                    file_path,
+                    temp_path,
+                    reserved: Arc::clone(reserved),
+                }));
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(format!("Failed to create file: {}", e)),
+        }
+    }
+}
+
+// 重试时沿用原路径和已有的 .part 文件
+pub fn reserve_existing(
+    reserved: &ReservedNames,
+    file_path: &Path,
+) -> Result<FilenameReservation, String> {
+    let mut names = reserved.lock().map_err(|e| e.to_string())?;
+
+    if !names.insert(file_path.to_path_buf()) {
+        return Err("Download already in progress".to_string());
+    }
+
+    Ok(FilenameReservation {
+        filename: file_path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        file_path: file_path.to_path_buf(),
+        temp_path: temp_path_for(file_path),
+        reserved: Arc::clone(reserved),
+    })
+}
+
+// 把已完成的文件移到另一个目录，重名时加序号；不检查下载记录，文件自己的记录必然同名
+pub fn move_to_dir(reserved: &ReservedNames, file: &Path, dir: &Path) -> Result<PathBuf, String> {
+    let names = reserved.lock().map_err(|e| e.to_string())?;
+    let filename = file
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .ok_or("Invalid path")?;
+
+    let target = dir.join(next_free_name(&filename, |name| {
+        let path = dir.join(name);
+        names.contains(&path) || path.exists() || temp_path_for(&path).exists()
+    }));
+    std::fs::rename(file, &target).map_err(|e| format!("Failed to move file: {}", e))?;
+
+    Ok(target)
+}
+
+fn next_free_name(filename: &str, is_name_taken: impl Fn(&str) -> bool) -> String {
+    if !is_name_taken(filename) {
+        return filename.to_string();
+    }
+
+    let path = PathBuf::from(filename);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+    let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("");
+
+    let mut counter = 1;
+    loop {
+        let new_name = if ext.is_empty() {
+            format!("{}({})", stem, counter)
+        } else {
+            format!("{}({}).{}", stem, counter, ext)
+        };
+
+        if !is_name_taken(&new_name) {
+            return new_name;
+        }
+        counter += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_rejects_path_traversal() {
+        assert_eq!(sanitize_filename("../../data/x"), "_.._data_x");
+        assert_eq!(sanitize_filename(".."), FALLBACK_FILENAME);
+        assert_eq!(sanitize_filename("."), FALLBACK_FILENAME);
+    }
+
+    #[test]
+    fn sanitize_replaces_separators() {
+        assert_eq!(sanitize_filename("a/b\\c.txt"), "a_b_c.txt");
+        assert_eq!(sanitize_filename("/etc/passwd"), "_etc_passwd");
+        assert_eq!(sanitize_filename("C:\\Windows\\x.dll"), "C__Windows_x.dll");
+    }
+
+    #[test]
+    fn sanitize_strips_control_and_bidi_characters() {
+        assert_eq!(sanitize_filename("a\u{0}b\nc\r.txt"), "abc.txt");
+        assert_eq!(sanitize_filename("invoice\u{202E}fdp.exe"), "invoicefdp.exe");
+        assert_eq!(sanitize_filename("\u{2066}x\u{2069}.png"), "x.png");
+    }
+
+    #[test]
+    fn sanitize_escapes_windows_device_names() {
+        assert_eq!(sanitize_filename("CON.txt"), "_CON.txt");
+        assert_eq!(sanitize_filename("com1"), "_com1");
+        assert_eq!(sanitize_filename("console.txt"), "console.txt");
+    }
+
+    #[test]
+    fn sanitize_normalizes_to_nfc() {
+        assert_eq!(sanitize_filename("e\u{0301}te\u{0301}.txt"), "\u{e9}t\u{e9}.txt");
+    }
+
+    #[test]
+    fn sanitize_falls_back_for_empty_names() {
+        assert_eq!(sanitize_filename(""), FALLBACK_FILENAME);
+        assert_eq!(sanitize_filename("   "), FALLBACK_FILENAME);
+        assert_eq!(sanitize_filename(" . \t"), FALLBACK_FILENAME);
+    }
+
+    #[test]
+    fn sanitize_truncates_long_names_keeping_extension() {
+        let name = format!("{}.txt", "中".repeat(100));
+        let sanitized = sanitize_filename(&name);
+
+        assert!(sanitized.len() <= MAX_FILENAME_BYTES);
+        let stem = sanitized.strip_suffix(".txt").expect("extension kept");
+        assert!(!stem.is_empty());
+        assert!(stem.chars().all(|c| c == '中'));
+    }
+
+    #[test]
+    fn final_name_keeps_extension_for_weak_signatures() {
+        let keep = |name: &str, sniffed: &str| {
+            assert_eq!(resolve_final_name(name, false, None, Some(sniffed), None), name);
+        };
+        keep("song.m4a", "video/mp4");
+        keep("clip.ogv", "audio/ogg");
+        keep("voice.weba", "video/x-matroska");
+        keep("notes.txt", "image/bmp");
+        keep("data.csv", "application/vnd.microsoft.portable-executable");
+    }
+
+    #[test]
+    fn final_name_replaces_extension_for_distinctive_signatures() {
+        let resolve =
+            |name: &str, sniffed: &str| resolve_final_name(name, false, None, Some(sniffed), None);
+        assert_eq!(resolve("report.txt", "application/pdf"), "report.pdf");
+        assert_eq!(resolve("photo.mp3", "image/png"), "photo.png");
+        // 同一大类不替换
+        assert_eq!(resolve("photo.png", "image/jpeg"), "photo.png");
+    }
+
+    #[test]
+    fn final_name_appends_missing_extension() {
+        assert_eq!(resolve_final_name("photo", false, None, Some("image/jpeg"), None), "photo.jpg");
+        assert_eq!(
+            resolve_final_name("report", false, None, None, Some("application/pdf")),
+            "report.pdf"
+        );
+        assert_eq!(resolve_final_name("download", true, Some("报告.pdf"), None, None), "报告.pdf");
+    }
+
+    #[test]
+    fn content_disposition_prefers_extended_filename() {
+        let header = b"attachment; filename=\"fallback.txt\"; filename*=UTF-8''%E4%B8%AD%E6%96%87.txt";
+        assert_eq!(parse_content_disposition(header).as_deref(), Some("中文.txt"));
+        assert_eq!(
+            parse_content_disposition(b"attachment; filename*=utf-8'zh'%E4%B8%AD.txt").as_deref(),
+            Some("中.txt")
+        );
+    }
+
+    #[test]
+    fn content_disposition_handles_quoted_names() {
+        assert_eq!(
+            parse_content_disposition(b"attachment; filename=\"a;b.txt\"; size=3").as_deref(),
+            Some("a;b.txt")
+        );
+        assert_eq!(
+            parse_content_disposition(b"attachment; filename=\"say \\\"hi\\\".txt\"").as_deref(),
+            Some("say \"hi\".txt")
+        );
+    }
+
+    #[test]
+    fn content_disposition_decodes_percent_encoded_plain_filename() {
+        assert_eq!(
+            parse_content_disposition(b"attachment; filename=%E4%B8%AD%E6%96%87.txt").as_deref(),
+            Some("中文.txt")
+        );
+        assert_eq!(
+            parse_content_disposition(b"attachment; filename=plain.txt").as_deref(),
+            Some("plain.txt")
+        );
+        assert_eq!(parse_content_disposition(b"inline"), None);
+        assert_eq!(parse_content_disposition(b"attachment; filename=\"\""), None);
+    }
+
+    #[test]
+    fn truncate_without_extension_stays_on_char_boundary() {
+        let truncated = truncate_filename(&"文".repeat(80), 100);
+        assert!(truncated.len() <= 100);
+        assert!(truncated.chars().all(|c| c == '文'));
+    }
+}
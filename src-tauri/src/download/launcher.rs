@@ -0,0 +1,332 @@
+use std::path::Path;
+use tauri::AppHandle;
+use tauri_plugin_opener::OpenerExt;
+
+use super::OpenWithApp;
+
+fn ensure_exists(path: &str) -> Result<(), String> {
+    if Path::new(path).exists() {
+        Ok(())
+    } else {
+        Err("File not found".to_string())
+    }
+}
+
+// 使用系统默认应用打开，目录会在文件管理器中打开
+pub fn open(app: &AppHandle, path: &str) -> Result<(), String> {
+    ensure_exists(path)?;
+    app.opener()
+        .open_path(path, None::<&str>)
+        .map_err(|e| format!("Failed to open file: {}", e))
+}
+
+pub fn reveal(app: &AppHandle, path: &str) -> Result<(), String> {
+    ensure_exists(path)?;
+    app.opener()
+        .reveal_item_in_dir(path)
+        .map_err(|e| format!("Failed to reveal file: {}", e))
+}
+
+// 指定应用时用它打开；未指定时显示系统的"打开方式"选择界面，没有时返回错误而不是改为在文件夹中显示
+pub fn open_with(app: &AppHandle, path: &str, application: Option<&str>) -> Result<(), String> {
+    ensure_exists(path)?;
+
+    match application.filter(|a| !a.trim().is_empty()) {
+        Some(application) => launch(app, path, application),
+        None => show_chooser(app, path),
+    }
+}
+
+// gio 启动应用后即退出，.desktop 文件无效或应用无法启动时返回非零状态
+#[cfg(target_os = "linux")]
+fn launch(_app: &AppHandle, path: &str, application: &str) -> Result<(), String> {
+    // id 是 .desktop 文件路径，由 gio 按其中的 Exec 启动
+    let output = std::process::Command::new("gio")
+        .args(["launch", application, path])
+        .output()
+        .map_err(|e| format!("Failed to launch application: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Failed to launch application: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+// 应用 id 是 .app 包的路径
+#[cfg(target_os = "macos")]
+fn launch(_app: &AppHandle, path: &str, application: &str) -> Result<(), String> {
+    let output = std::process::Command::new("open")
+        .arg("-a")
+        .arg(application)
+        .arg(path)
+        .output()
+        .map_err(|e| format!("Failed to launch application: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Failed to launch application: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+// Windows 上应用 id 是可执行文件名，由系统按 App Paths 查找
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn launch(app: &AppHandle, path: &str, application: &str) -> Result<(), String> {
+    app.opener()
+        .open_path(path, Some(application))
+        .map_err(|e| format!("Failed to launch application: {}", e))
+}
+
+#[cfg(target_os = "windows")]
+fn show_chooser(_app: &AppHandle, path: &str) -> Result<(), String> {
+    std::process::Command::new("rundll32.exe")
+        .arg("shell32.dll,OpenAs_RunDLL")
+        .arg(path)
+        .spawn()
+        .map(|_| ())
+        .map_err(|e| format!("Failed to show application chooser: {}", e))
+}
+
+// 其他桌面系统没有可直接调用的选择界面，由前端用 list_apps 的结果让用户选择
+#[cfg(not(target_os = "windows"))]
+fn show_chooser(_app: &AppHandle, _path: &str) -> Result<(), String> {
+    Err("No application chooser available on this platform".to_string())
+}
+
+// 按系统的 MIME 关联列出可用应用，默认应用排在最前
+#[cfg(target_os = "linux")]
+pub fn list_apps(_path: &str, mime: &str) -> Vec<OpenWithApp> {
+    // 固定为英文输出，便于解析
+    let output = match std::process::Command::new("gio")
+        .args(["mime", mime])
+        .env("LC_ALL", "C")
+        .output()
+    {
+        Ok(output) if output.status.success() => output,
+        _ => return Vec::new(),
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    let mut default = None;
+    let mut ids: Vec<String> = Vec::new();
+    let mut in_registered = false;
+    for line in stdout.lines() {
+        if let Some(rest) = line.strip_prefix("Default application for") {
+            default = rest.rsplit(':').next().map(|s| s.trim().to_string());
+        } else if line.starts_with("Registered applications") {
+            in_registered = true;
+        } else if let Some(id) = line.strip_prefix('\t').filter(|_| in_registered) {
+            let id = id.trim().to_string();
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        } else {
+            in_registered = false;
+        }
+    }
+
+    let mut apps: Vec<OpenWithApp> = ids
+        .into_iter()
+        .filter_map(|id| {
+            let file = find_desktop_file(&id)?;
+            let name = desktop_entry_name(&file)
+                .unwrap_or_else(|| id.trim_end_matches(".desktop").to_string());
+            Some(OpenWithApp {
+                is_default: default.as_deref() == Some(id.as_str()),
+                id: file.to_string_lossy().to_string(),
+                name,
+            })
+        })
+        .collect();
+    apps.sort_by_key(|app| !app.is_default);
+    apps
+}
+
+// 通过 JXA 调用 NSWorkspace 向 Launch Services 查询能打开该文件的应用，第一行是默认应用；
+// 该接口需要 macOS 12，更早的系统上脚本出错，返回空列表
+#[cfg(target_os = "macos")]
+const LAUNCH_SERVICES_SCRIPT: &str = r#"
+ObjC.import('AppKit');
+function run(argv) {
+    const workspace = $.NSWorkspace.sharedWorkspace;
+    const url = $.NSURL.fileURLWithPath(argv[0]);
+    const preferred = workspace.URLForApplicationToOpenURL(url);
+    const apps = workspace.URLsForApplicationsToOpenURL(url);
+    const paths = [preferred.isNil() ? '' : preferred.path.js];
+    for (let i = 0; i < apps.count; i++) {
+        paths.push(apps.objectAtIndex(i).path.js);
+    }
+    return paths.join('\n');
+}
+"#;
+
+// Launch Services 按文件本身（扩展名和 UTI）查询，不使用 mime
+#[cfg(target_os = "macos")]
+pub fn list_apps(path: &str, _mime: &str) -> Vec<OpenWithApp> {
+    let output = match std::process::Command::new("osascript")
+        .args(["-l", "JavaScript", "-e", LAUNCH_SERVICES_SCRIPT, path])
+        .output()
+    {
+        Ok(output) if output.status.success() => output,
+        _ => return Vec::new(),
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    let mut lines = stdout.lines().map(str::trim);
+    let default = lines.next().unwrap_or_default().to_string();
+    let mut ids: Vec<String> = Vec::new();
+    for id in lines.filter(|line| !line.is_empty()) {
+        if !ids.iter().any(|existing| existing == id) {
+            ids.push(id.to_string());
+        }
+    }
+
+    let mut apps: Vec<OpenWithApp> = ids
+        .into_iter()
+        .map(|id| OpenWithApp {
+            name: Path::new(&id)
+                .file_stem()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            is_default: id == default,
+            id,
+        })
+        .collect();
+    apps.sort_by_key(|app| !app.is_default);
+    apps
+}
+
+// 资源管理器"打开方式"用过的应用和安装程序注册的应用，按扩展名记录在注册表中
+#[cfg(target_os = "windows")]
+pub fn list_apps(path: &str, _mime: &str) -> Vec<OpenWithApp> {
+    let Some(ext) = Path::new(path).extension().map(|e| e.to_string_lossy().to_string()) else {
+        return Vec::new();
+    };
+
+    let user_list = reg_values(&format!(
+        r"HKCU\Software\Microsoft\Windows\CurrentVersion\Explorer\FileExts\.{}\OpenWithList",
+        ext
+    ))
+    .into_iter()
+    .map(|(_, value)| value);
+    let registered = reg_subkeys(&format!(r"HKCR\.{}\OpenWithList", ext));
+
+    let mut exes: Vec<String> = Vec::new();
+    for exe in user_list.chain(registered) {
+        // OpenWithList 中还有记录顺序的 MRUList 等值
+        let is_exe = exe.to_ascii_lowercase().ends_with(".exe");
+        if is_exe && !exes.iter().any(|e| e.eq_ignore_ascii_case(&exe)) {
+            exes.push(exe);
+        }
+    }
+
+    exes.into_iter()
+        .map(|exe| {
+            let name = reg_values(&format!(r"HKCR\Applications\{}", exe))
+                .into_iter()
+                .find(|(name, _)| name == "FriendlyAppName")
+                .map(|(_, value)| value)
+                .filter(|value| !value.is_empty() && !value.starts_with('@'))
+                .unwrap_or_else(|| exe.trim_end_matches(".exe").to_string());
+            OpenWithApp {
+                id: exe,
+                name,
+                is_default: false,
+            }
+        })
+        .collect()
+}
+
+#[cfg(target_os = "windows")]
+fn reg_query(key: &str) -> Option<String> {
+    let output = std::process::Command::new("reg")
+        .args(["query", key])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    Some(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+// 解析 "    名称    REG_SZ    值" 形式的行
+#[cfg(target_os = "windows")]
+fn reg_values(key: &str) -> Vec<(String, String)> {
+    let Some(output) = reg_query(key) else {
+        return Vec::new();
+    };
+
+    output
+        .lines()
+        .filter_map(|line| {
+            let pos = line.find("    REG_")?;
+            let name = line[..pos].trim().to_string();
+            let value = line[pos..]
+                .trim_start()
+                .split_once("    ")
+                .map(|(_, value)| value.trim().to_string())
+                .unwrap_or_default();
+            Some((name, value))
+        })
+        .collect()
+}
+
+// 子键以完整路径列出，第一行是键本身
+#[cfg(target_os = "windows")]
+fn reg_subkeys(key: &str) -> Vec<String> {
+    let Some(output) = reg_query(key) else {
+        return Vec::new();
+    };
+
+    output
+        .lines()
+        .filter(|line| line.starts_with("HKEY_"))
+        .skip(1)
+        .filter_map(|line| line.trim().rsplit('\\').next().map(str::to_string))
+        .collect()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+pub fn list_apps(_path: &str, _mime: &str) -> Vec<OpenWithApp> {
+    Vec::new()
+}
+
+#[cfg(target_os = "linux")]
+fn find_desktop_file(id: &str) -> Option<std::path::PathBuf> {
+    let data_home = std::env::var("XDG_DATA_HOME")
+        .ok()
+        .filter(|s| !s.is_empty())
+        .or_else(|| {
+            std::env::var("HOME")
+                .ok()
+                .map(|home| format!("{}/.local/share", home))
+        });
+    let data_dirs = std::env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+
+    data_home
+        .into_iter()
+        .chain(data_dirs.split(':').map(str::to_string))
+        .map(|dir| Path::new(&dir).join("applications").join(id))
+        .find(|file| file.is_file())
+}
+
+#[cfg(target_os = "linux")]
+fn desktop_entry_name(file: &Path) -> Option<String> {
+    let content = std::fs::read_to_string(file).ok()?;
+    let mut in_entry = false;
+    for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_entry = line == "[Desktop Entry]";
+        } else if let Some(name) = line.strip_prefix("Name=").filter(|_| in_entry) {
+            return Some(name.to_string());
+        }
+    }
+    None
+}
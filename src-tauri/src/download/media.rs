@@ -0,0 +1,288 @@
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Manager};
+
+// 缩略图最长边，列表中按 2 倍像素显示也足够清晰
+const THUMBNAIL_SIZE: u32 = 256;
+// 超过该大小的图片不解码，避免占用过多内存
+const MAX_IMAGE_BYTES: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MediaInfo {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    // EXIF 中的拍摄时间，原样保存为 "YYYY:MM:DD HH:MM:SS"
+    pub taken_at: Option<String>,
+    pub duration_ms: Option<u64>,
+    pub thumbnail: bool,
+}
+
+// 与启用的 image 解码器对应，SVG、HEIC 等格式无法生成缩略图
+const DECODABLE_IMAGES: &[&str] = &[
+    "image/jpeg",
+    "image/png",
+    "image/gif",
+    "image/webp",
+    "image/bmp",
+    "image/tiff",
+];
+
+pub fn is_media(mime: &str) -> bool {
+    mime.starts_with("image/") || mime.starts_with("audio/") || mime.starts_with("video/")
+}
+
+// 音视频能否生成取决于文件内容和是否安装了 ffmpeg，只能尝试后才知道
+pub fn is_supported(mime: &str) -> bool {
+    DECODABLE_IMAGES.contains(&mime) || mime.starts_with("audio/") || mime.starts_with("video/")
+}
+
+// 缓存目录可能被系统清理，调用方需要在文件不存在时重新生成；id 会成为文件名，只接受记录 ID 的字符
+pub fn thumbnail_path(app: &AppHandle, id: &str) -> Result<PathBuf, String> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err("Invalid download id".to_string());
+    }
+    let cache = app.path().app_cache_dir().map_err(|e| e.to_string())?;
+    Ok(cache.join("thumbnails").join(format!("{}.jpg", id)))
+}
+
+// 能生成缩略图时写入 thumbnail
+pub fn generate(path: &Path, mime: &str, thumbnail: &Path) -> Result<MediaInfo, String> {
+    if let Some(parent) = thumbnail.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create folder: {}", e))?;
+    }
+
+    let info = if !is_supported(mime) {
+        return Ok(MediaInfo::default());
+    } else if mime.starts_with("image/") {
+        image_info(path, thumbnail)?
+    } else if mime == "audio/mpeg" {
+        mp3_info(path, thumbnail)
+    } else {
+        MediaInfo {
+            duration_ms: mp4_duration_ms(path),
+            ..Default::default()
+        }
+    };
+
+    let info = if mime.starts_with("image/") {
+        info
+    } else {
+        with_ffmpeg(path, thumbnail, info)
+    };
+
+    if !info.thumbnail {
+        let _ = std::fs::remove_file(thumbnail);
+    }
+    Ok(info)
+}
+
+fn image_info(path: &Path, thumbnail: &Path) -> Result<MediaInfo, String> {
+    let size = std::fs::metadata(path).map_err(|_| "File not found".to_string())?.len();
+    if size > MAX_IMAGE_BYTES {
+        return Err("Image too large".to_string());
+    }
+
+    let reader = ImageReader::open(path)
+        .and_then(|r| r.with_guessed_format())
+        .map_err(|e| format!("Failed to open image: {}", e))?;
+    // HEIC 等格式没有可用的解码器，只是不生成缩略图
+    let mut decoder = match reader.into_decoder() {
+        Ok(decoder) => decoder,
+        Err(image::ImageError::Unsupported(_)) => return Ok(MediaInfo::default()),
+        Err(e) => return Err(format!("Invalid image: {}", e)),
+    };
+
+    let taken_at = decoder
+        .exif_metadata()
+        .ok()
+        .flatten()
+        .and_then(|exif| exif_taken_at(&exif));
+    let orientation = decoder.orientation().ok();
+
+    let mut image = DynamicImage::from_decoder(decoder).map_err(|e| format!("Invalid image: {}", e))?;
+    if let Some(orientation) = orientation {
+        image.apply_orientation(orientation);
+    }
+
+    Ok(MediaInfo {
+        width: Some(image.width()),
+        height: Some(image.height()),
+        taken_at,
+        duration_ms: None,
+        thumbnail: save_thumbnail(&image, thumbnail)?,
+    })
+}
+
+// mp3 的时长和封面都来自 ID3 标签，TLEN 以毫秒为单位
+fn mp3_info(path: &Path, thumbnail: &Path) -> MediaInfo {
+    use id3::TagLike;
+
+    let Ok(tag) = id3::Tag::read_from_path(path) else {
+        return MediaInfo::default();
+    };
+
+    let cover = tag
+        .pictures()
+        .find(|p| p.picture_type == id3::frame::PictureType::CoverFront)
+        .or_else(|| tag.pictures().next())
+        .and_then(|p| image::load_from_memory(&p.data).ok());
+
+    MediaInfo {
+        duration_ms: tag.duration().map(u64::from),
+        thumbnail: cover.is_some_and(|image| save_thumbnail(&image, thumbnail).unwrap_or(false)),
+        ..Default::default()
+    }
+}
+
+fn save_thumbnail(image: &DynamicImage, thumbnail: &Path) -> Result<bool, String> {
+    image
+        .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+        .to_rgb8()
+        .save_with_format(thumbnail, ImageFormat::Jpeg)
+        .map_err(|e| format!("Failed to save thumbnail: {}", e))?;
+    Ok(true)
+}
+
+// 只取拍摄时间：IFD0 -> Exif IFD(0x8769) -> DateTimeOriginal(0x9003)，没有时退而使用 IFD0 的 DateTime(0x0132)
+fn exif_taken_at(exif: &[u8]) -> Option<String> {
+    let tiff = exif.strip_prefix(b"Exif\0\0").unwrap_or(exif);
+    let little = match tiff.get(..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+
+    let u16_at = |offset: usize| {
+        tiff.get(offset..offset + 2).map(|b| {
+            let bytes = [b[0], b[1]];
+            if little { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) }
+        })
+    };
+    let u32_at = |offset: usize| {
+        tiff.get(offset..offset + 4).map(|b| {
+            let bytes = [b[0], b[1], b[2], b[3]];
+            if little { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) }
+        })
+    };
+    // 返回条目的值字段，长度超过 4 字节的值在这里是偏移
+    let find = |ifd: u32, tag: u16| -> Option<u32> {
+        let ifd = ifd as usize;
+        let count = u16_at(ifd)? as usize;
+        (0..count)
+            .map(|i| ifd + 2 + i * 12)
+            .find(|&entry| u16_at(entry) == Some(tag))
+            .and_then(|entry| u32_at(entry + 8))
+    };
+    let ascii = |offset: u32| -> Option<String> {
+        let offset = offset as usize;
+        let bytes = tiff.get(offset..offset + 19)?;
+        std::str::from_utf8(bytes).ok().map(str::to_string)
+    };
+
+    let ifd0 = u32_at(4)?;
+    find(ifd0, 0x8769)
+        .and_then(|exif_ifd| find(exif_ifd, 0x9003))
+        .and_then(ascii)
+        .or_else(|| find(ifd0, 0x0132).and_then(ascii))
+        // 部分设备未设置时间时写入全零
+        .filter(|s| !s.starts_with("0000"))
+}
+
+// mp4/mov 的时长在 moov/mvhd 中，不需要解码
+fn mp4_duration_ms(path: &Path) -> Option<u64> {
+    let mut file = File::open(path).ok()?;
+    let len = file.metadata().ok()?.len();
+    let (start, end) = find_box(&mut file, 0, len, b"moov")?;
+    let (mvhd, _) = find_box(&mut file, start, end, b"mvhd")?;
+
+    let mut buf = [0u8; 32];
+    file.seek(SeekFrom::Start(mvhd)).ok()?;
+    file.read_exact(&mut buf).ok()?;
+
+    let be32 = |o: usize| u32::from_be_bytes([buf[o], buf[o + 1], buf[o + 2], buf[o + 3]]);
+    // version 1 的时间字段为 64 位
+    let (timescale, duration) = if buf[0] == 1 {
+        (be32(20), (u64::from(be32(24)) << 32) | u64::from(be32(28)))
+    } else {
+        (be32(12), u64::from(be32(16)))
+    };
+    (timescale > 0).then(|| duration.saturating_mul(1000) / u64::from(timescale))
+}
+
+// 在 [pos, end) 范围内查找指定类型的 box，返回其内容范围
+fn find_box(file: &mut File, mut pos: u64, end: u64, kind: &[u8; 4]) -> Option<(u64, u64)> {
+    while pos + 8 <= end {
+        let mut header = [0u8; 8];
+        file.seek(SeekFrom::Start(pos)).ok()?;
+        file.read_exact(&mut header).ok()?;
+
+        let mut size = u64::from(u32::from_be_bytes([header[0], header[1], header[2], header[3]]));
+        let mut header_len = 8;
+        if size == 1 {
+            let mut large = [0u8; 8];
+            file.read_exact(&mut large).ok()?;
+            size = u64::from_be_bytes(large);
+            header_len = 16;
+        } else if size == 0 {
+            size = end - pos;
+        }
+        if size < header_len {
+            return None;
+        }
+
+        if &header[4..8] == kind {
+            return Some((pos + header_len, pos.saturating_add(size).min(end)));
+        }
+        pos = pos.saturating_add(size);
+    }
+    None
+}
+
+// 系统安装了 ffmpeg 时用它补全其他格式的时长、视频首帧和音频封面
+#[cfg(not(target_os = "android"))]
+fn with_ffmpeg(path: &Path, thumbnail: &Path, mut info: MediaInfo) -> MediaInfo {
+    if info.duration_ms.is_none() {
+        info.duration_ms = ffprobe_duration_ms(path);
+    }
+    if !info.thumbnail {
+        info.thumbnail = ffmpeg_frame(path, thumbnail);
+    }
+    info
+}
+
+#[cfg(target_os = "android")]
+fn with_ffmpeg(_path: &Path, _thumbnail: &Path, info: MediaInfo) -> MediaInfo {
+    info
+}
+
+#[cfg(not(target_os = "android"))]
+fn ffprobe_duration_ms(path: &Path) -> Option<u64> {
+    let output = std::process::Command::new("ffprobe")
+        .args(["-v", "error", "-show_entries", "format=duration", "-of", "csv=p=0"])
+        .arg(path)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let secs: f64 = String::from_utf8_lossy(&output.stdout).trim().parse().ok()?;
+    (secs.is_finite() && secs > 0.0).then_some((secs * 1000.0) as u64)
+}
+
+// 视频取第一帧，音频取内嵌封面（ffmpeg 把封面当作视频流）
+#[cfg(not(target_os = "android"))]
+fn ffmpeg_frame(path: &Path, thumbnail: &Path) -> bool {
+    let scale = format!(
+        "scale={size}:{size}:force_original_aspect_ratio=decrease",
+        size = THUMBNAIL_SIZE
+    );
+    std::process::Command::new("ffmpeg")
+        .args(["-v", "error", "-y", "-i"])
+        .arg(path)
+        .args(["-frames:v", "1", "-vf", &scale, "-f", "image2", "-c:v", "mjpeg"])
+        .arg(thumbnail)
+        .output()
+        .is_ok_and(|output| output.status.success() && thumbnail.is_file())
+}
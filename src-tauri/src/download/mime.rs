@@ -0,0 +1,143 @@
+use std::io::Read;
+use std::path::Path;
+
+// 足够覆盖下表中所有特征的偏移
+const SNIFF_LEN: usize = 64;
+
+// (偏移, 特征字节, MIME)，按顺序匹配
+const SIGNATURES: &[(usize, &[u8], &str)] = &[
+    (0, b"\x89PNG\r\n\x1a\n", "image/png"),
+    (0, b"\xff\xd8\xff", "image/jpeg"),
+    (0, b"GIF87a", "image/gif"),
+    (0, b"GIF89a", "image/gif"),
+    (0, b"BM", "image/bmp"),
+    (0, b"II*\x00", "image/tiff"),
+    (0, b"MM\x00*", "image/tiff"),
+    (4, b"ftypheic", "image/heic"),
+    (4, b"ftypheix", "image/heic"),
+    (4, b"ftypmif1", "image/heif"),
+    (4, b"ftypavif", "image/avif"),
+    (4, b"ftypM4A ", "audio/mp4"),
+    (4, b"ftypqt  ", "video/quicktime"),
+    (4, b"ftyp", "video/mp4"),
+    (0, b"\x1a\x45\xdf\xa3", "video/x-matroska"),
+    (0, b"ID3", "audio/mpeg"),
+    (0, b"\xff\xfb", "audio/mpeg"),
+    (0, b"\xff\xf3", "audio/mpeg"),
+    (0, b"fLaC", "audio/flac"),
+    (0, b"OggS", "audio/ogg"),
+    (0, b"%PDF-", "application/pdf"),
+    (0, b"PK\x03\x04", "application/zip"),
+    (0, b"\x1f\x8b", "application/gzip"),
+    (0, b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (0, b"Rar!\x1a\x07", "application/vnd.rar"),
+    (0, b"MZ", "application/vnd.microsoft.portable-executable"),
+];
+
+// RIFF 容器的具体类型在偏移 8 处
+const RIFF_TYPES: &[(&[u8], &str)] = &[
+    (b"WEBP", "image/webp"),
+    (b"WAVE", "audio/wav"),
+    (b"AVI ", "video/x-msvideo"),
+];
+
+pub fn sniff(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"RIFF") && bytes.len() >= 12 {
+        return RIFF_TYPES
+            .iter()
+            .find(|(tag, _)| &bytes[8..12] == *tag)
+            .map(|(_, mime)| *mime);
+    }
+
+    SIGNATURES
+        .iter()
+        .find(|(offset, magic, _)| bytes.get(*offset..offset + magic.len()) == Some(*magic))
+        .map(|(_, _, mime)| *mime)
+}
+
+pub fn sniff_file(path: &Path) -> Option<&'static str> {
+    let mut buf = Vec::with_capacity(SNIFF_LEN);
+    std::fs::File::open(path)
+        .ok()?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut buf)
+        .ok()?;
+    sniff(&buf)
+}
+
+// 特征足够长且不是通用容器，可以据此判定扩展名有误；ftyp、Ogg、EBML 等容器既可能是音频也可能是视频，
+// BM、MZ 等两字节特征容易与普通文本开头重合，只用于补全缺失的扩展名
+const DISTINCTIVE: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "application/pdf",
+    "application/x-7z-compressed",
+    "application/vnd.rar",
+];
+
+pub fn is_distinctive(mime: &str) -> bool {
+    DISTINCTIVE.contains(&mime)
+}
+
+// 服务器常用 application/octet-stream 表示"未知"，不能据此选择扩展名
+pub fn is_specific(mime: &str) -> bool {
+    !mime.is_empty() && mime != "application/octet-stream" && mime != "binary/octet-stream"
+}
+
+pub fn extension_for(mime: &str) -> Option<&'static str> {
+    match mime {
+        // mime_guess 对这些类型给出的第一个扩展名不常用
+        "image/jpeg" => Some("jpg"),
+        "audio/mpeg" => Some("mp3"),
+        "video/mp4" => Some("mp4"),
+        "audio/mp4" => Some("m4a"),
+        "video/quicktime" => Some("mov"),
+        "text/plain" => Some("txt"),
+        "application/vnd.microsoft.portable-executable" => Some("exe"),
+        _ => mime_guess::get_mime_extensions_str(mime)?.first().copied(),
+    }
+}
+
+// 按文件名推断，没有扩展名或无法识别时为 application/octet-stream
+pub fn from_name(name: &str) -> String {
+    mime_guess::from_path(name)
+        .first_or_octet_stream()
+        .essence_str()
+        .to_string()
+}
+
+// 强特征最可信，其次是服务器声明的具体类型和扩展名，弱特征只在前面都无法判断时使用
+pub fn resolve(path: &Path, declared: Option<&str>) -> String {
+    let sniffed = sniff_file(path);
+    sniffed
+        .filter(|m| is_distinctive(m))
+        .or_else(|| declared.filter(|m| is_specific(m)))
+        .map(str::to_string)
+        .or_else(|| Some(from_name(&path.to_string_lossy())).filter(|m| is_specific(m)))
+        .or_else(|| sniffed.map(str::to_string))
+        .unwrap_or_else(|| "application/octet-stream".to_string())
+}
+
+// 下载目录按类别分组时使用的文件夹名
+pub fn category(mime: &str) -> &'static str {
+    let (type_, subtype) = mime.split_once('/').unwrap_or((mime, ""));
+
+    match (type_, subtype) {
+        ("image", _) => "Images",
+        ("video", _) => "Videos",
+        ("audio", _) => "Music",
+        ("text", _) | ("application", "pdf") => "Documents",
+        ("application", sub)
+            if sub.contains("document")
+                || sub.contains("msword")
+                || sub.contains("sheet")
+                || sub.contains("presentation") =>
+        {
+            "Documents"
+        }
+        ("application", "zip" | "gzip" | "x-tar" | "x-7z-compressed" | "vnd.rar") => "Archives",
+        ("application", "vnd.android.package-archive") => "Apps",
+        _ => "Others",
+    }
+}
@@ -0,0 +1,299 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Emitter};
+
+use super::settings::GroupBy;
+use super::{
+    archive_kind, extract_archive, filename, generate_media, media, mime, DownloadRecord,
+    DownloadSettings, DownloadState,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StepState {
+    Succeeded,
+    Skipped,
+    Failed,
+}
+
+// 每个已启用步骤的执行结果，保存在下载记录中
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepStatus {
+    pub step: String,
+    pub state: StepState,
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+pub enum HookOutcome {
+    Done(Option<String>),
+    // 已启用但对该文件不适用，例如不是压缩包
+    Skipped(String),
+}
+
+pub struct HookContext<'a> {
+    pub app: &'a AppHandle,
+    pub state: &'a DownloadState,
+    pub settings: &'a DownloadSettings,
+    pub record: DownloadRecord,
+}
+
+// 下载完成后依次执行的处理步骤，在阻塞线程中同步运行；哈希校验在改名前进行，不在其中
+pub trait PostDownloadHook: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // 未启用的步骤不执行，也不写入记录
+    fn enabled(&self, ctx: &HookContext) -> bool;
+
+    fn run(&self, ctx: &mut HookContext) -> Result<HookOutcome, String>;
+
+    // 把本步骤修改的字段写回存储中的记录，其余字段可能已被用户改名或删除，不能整条覆盖
+    fn save(&self, _ctx: &HookContext, _record: &mut DownloadRecord) {}
+}
+
+pub fn default_hooks() -> Vec<Box<dyn PostDownloadHook>> {
+    vec![
+        Box::new(MoveToCategory),
+        Box::new(Extract),
+        Box::new(Thumbnail),
+        Box::new(ShellCommand),
+        Box::new(AutoOpen),
+    ]
+}
+
+// 每一步完成后都保存记录并发出 download-postprocess 事件，记录被删除时停止，返回处理后的记录
+pub fn run(hooks: &[Box<dyn PostDownloadHook>], mut ctx: HookContext) -> DownloadRecord {
+    for hook in hooks {
+        if !hook.enabled(&ctx) {
+            continue;
+        }
+
+        // 上一步执行期间记录可能已被改名或删除
+        match ctx.state.store.get(&ctx.record.id) {
+            Ok(Some(record)) => ctx.record = record,
+            Ok(None) => break,
+            Err(e) => log::error!("Failed to load download record {}: {}", ctx.record.id, e),
+        }
+
+        let (state, message) = match hook.run(&mut ctx) {
+            Ok(HookOutcome::Done(message)) => (StepState::Succeeded, message),
+            Ok(HookOutcome::Skipped(reason)) => (StepState::Skipped, Some(reason)),
+            Err(e) => {
+                log::error!("Post-download step {} failed for {}: {}", hook.name(), ctx.record.id, e);
+                (StepState::Failed, Some(e))
+            }
+        };
+
+        let status = StepStatus {
+            step: hook.name().to_string(),
+            state,
+            message: message.clone(),
+        };
+        ctx.record.post_processing.push(status.clone());
+        let saved = ctx.state.store.update(&ctx.record.id, |record| {
+            hook.save(&ctx, record);
+            record.post_processing.push(status);
+            Ok(())
+        });
+        if let Err(e) = saved {
+            log::error!("Failed to save download record {}: {}", ctx.record.id, e);
+            if !matches!(ctx.state.store.get(&ctx.record.id), Ok(Some(_))) {
+                break;
+            }
+        }
+
+        ctx.app
+            .emit(
+                "download-postprocess",
+                serde_json::json!({
+                    "id": ctx.record.id,
+                    "step": hook.name(),
+                    "state": state,
+                    "message": message,
+                }),
+            )
+            .unwrap_or(());
+    }
+
+    ctx.record
+}
+
+// 下载开始时只能按调用方给的文件名分类，完成后按内容确定的类型重新计算目录
+struct MoveToCategory;
+
+impl PostDownloadHook for MoveToCategory {
+    fn name(&self) -> &'static str {
+        "move"
+    }
+
+    fn enabled(&self, ctx: &HookContext) -> bool {
+        ctx.settings.group_by.contains(&GroupBy::Category)
+    }
+
+    fn run(&self, ctx: &mut HookContext) -> Result<HookOutcome, String> {
+        let record = &ctx.record;
+        let mime_type = record
+            .mime_type
+            .clone()
+            .unwrap_or_else(|| mime::resolve(Path::new(&record.path), None));
+        let root = ctx.settings.download_root(ctx.app)?;
+        let dir = ctx.settings.target_dir(
+            &root,
+            &mime_type,
+            record.sender_name.as_deref(),
+            &record.url,
+        );
+
+        let current = PathBuf::from(&record.path);
+        if current.parent() == Some(dir.as_path()) {
+            return Ok(HookOutcome::Skipped("Already in category folder".to_string()));
+        }
+
+        std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create folder: {}", e))?;
+        let target = filename::move_to_dir(&ctx.state.reserved_names, &current, &dir)?;
+
+        ctx.record.filename = target
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        ctx.record.path = target.to_string_lossy().to_string();
+        Ok(HookOutcome::Done(Some(ctx.record.path.clone())))
+    }
+
+    fn save(&self, ctx: &HookContext, record: &mut DownloadRecord) {
+        record.filename = ctx.record.filename.clone();
+        record.path = ctx.record.path.clone();
+    }
+}
+
+struct Extract;
+
+impl PostDownloadHook for Extract {
+    fn name(&self) -> &'static str {
+        "extract"
+    }
+
+    fn enabled(&self, ctx: &HookContext) -> bool {
+        ctx.settings.extract_archives
+    }
+
+    fn run(&self, ctx: &mut HookContext) -> Result<HookOutcome, String> {
+        if archive_kind(&ctx.record).is_none() {
+            return Ok(HookOutcome::Skipped("Not an archive".to_string()));
+        }
+
+        let records = extract_archive(ctx.app, ctx.state, &ctx.record)?;
+        Ok(HookOutcome::Done(Some(format!("{} files", records.len()))))
+    }
+}
+
+// 放在移动之后，使用文件的最终位置
+struct Thumbnail;
+
+impl PostDownloadHook for Thumbnail {
+    fn name(&self) -> &'static str {
+        "thumbnail"
+    }
+
+    fn enabled(&self, ctx: &HookContext) -> bool {
+        ctx.settings.thumbnails() && ctx.record.mime_type.as_deref().is_some_and(media::is_media)
+    }
+
+    fn run(&self, ctx: &mut HookContext) -> Result<HookOutcome, String> {
+        if !ctx.record.mime_type.as_deref().is_some_and(media::is_supported) {
+            return Ok(HookOutcome::Skipped("Unsupported format".to_string()));
+        }
+
+        generate_media(ctx.app, &mut ctx.record)?;
+        match &ctx.record.media {
+            Some(info) if info.thumbnail => Ok(HookOutcome::Done(None)),
+            _ => Ok(HookOutcome::Skipped("No decoder available".to_string())),
+        }
+    }
+
+    fn save(&self, ctx: &HookContext, record: &mut DownloadRecord) {
+        record.media = ctx.record.media.clone();
+    }
+}
+
+// 命令不经过 shell 解析，占位符按参数逐个替换，文件名中的特殊字符不会被当作命令执行
+struct ShellCommand;
+
+impl PostDownloadHook for ShellCommand {
+    fn name(&self) -> &'static str {
+        "command"
+    }
+
+    fn enabled(&self, ctx: &HookContext) -> bool {
+        cfg!(not(target_os = "android")) && !ctx.settings.post_download_command.is_empty()
+    }
+
+    #[cfg(not(target_os = "android"))]
+    fn run(&self, ctx: &mut HookContext) -> Result<HookOutcome, String> {
+        use tauri_plugin_shell::ShellExt;
+
+        let dir = Path::new(&ctx.record.path)
+            .parent().map(|d| d.to_string_lossy().to_string()).unwrap_or_default();
+        let args: Vec<String> = ctx
+            .settings
+            .post_download_command
+            .iter()
+            .map(|arg| {
+                arg.replace("{path}", &ctx.record.path)
+                    .replace("{name}", &ctx.record.filename)
+                    .replace("{dir}", &dir)
+            })
+            .collect();
+        let (program, args) = args.split_first().ok_or("Empty command")?;
+
+        let output = tauri::async_runtime::block_on(ctx.app.shell().command(program).args(args).output())
+            .map_err(|e| format!("Failed to run command: {}", e))?;
+
+        if output.status.success() {
+            Ok(HookOutcome::Done(None))
+        } else {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            Err(format!(
+                "Command exited with {}: {}",
+                output.status.code().map_or("signal".to_string(), |c| c.to_string()),
+                stderr.trim()
+            ))
+        }
+    }
+
+    #[cfg(target_os = "android")]
+    fn run(&self, _ctx: &mut HookContext) -> Result<HookOutcome, String> {
+        Ok(HookOutcome::Skipped("Not supported on this platform".to_string()))
+    }
+}
+
+// 放在最后，前面的步骤可能移动了文件
+struct AutoOpen;
+
+impl PostDownloadHook for AutoOpen {
+    fn name(&self) -> &'static str {
+        "open"
+    }
+
+    fn enabled(&self, ctx: &HookContext) -> bool {
+        ctx.settings.auto_open
+    }
+
+    fn run(&self, ctx: &mut HookContext) -> Result<HookOutcome, String> {
+        #[cfg(target_os = "android")]
+        {
+            let mime_type = super::intent_mime(ctx.state, &ctx.record.path);
+            tauri::async_runtime::block_on(super::open_file_android(
+                ctx.app,
+                &ctx.record.path,
+                &mime_type,
+                false,
+            ))?;
+        }
+
+        #[cfg(not(target_os = "android"))]
+        super::launcher::open(ctx.app, &ctx.record.path)?;
+
+        Ok(HookOutcome::Done(None))
+    }
+}
@@ -0,0 +1,95 @@
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+// 速度平滑的时间常数，越大越平稳但对速度变化反应越慢
+const SPEED_TIME_CONSTANT: f64 = 3.0;
+
+pub const DEFAULT_PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferState {
+    // 已创建任务，正在建立连接
+    Pending,
+    Downloading,
+    Paused,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+// 统计传输速度、耗时和剩余时间，暂停期间不计入耗时和速度；跨越暂停前后的多次连接
+#[derive(Default)]
+pub struct ProgressTracker {
+    // 平滑后的速度（字节/秒）
+    speed: f64,
+    last_sample: Option<(Instant, u64)>,
+    active_duration: Duration,
+    active_since: Option<Instant>,
+    // 各次连接实际传输的字节数之和，不含开始前已有的 .part 部分
+    transferred: u64,
+}
+
+impl ProgressTracker {
+    pub fn add_transferred(&mut self, bytes: u64) {
+        self.transferred += bytes;
+    }
+
+    pub fn transferred(&self) -> u64 {
+        self.transferred
+    }
+
+    pub fn pause(&mut self) {
+        if let Some(since) = self.active_since.take() {
+            self.active_duration += since.elapsed();
+        }
+        self.speed = 0.0;
+        self.last_sample = None;
+    }
+
+    pub fn resume(&mut self, received: u64) {
+        let now = Instant::now();
+        self.active_since.get_or_insert(now);
+        self.last_sample = Some((now, received));
+    }
+
+    // 事件间隔不固定，按实际间隔计算权重，间隔越长新样本占比越大
+    pub fn sample(&mut self, received: u64) {
+        let now = Instant::now();
+
+        if let Some((last_time, last_received)) = self.last_sample {
+            let dt = now.duration_since(last_time).as_secs_f64();
+            if dt <= 0.0 {
+                return;
+            }
+
+            let rate = received.saturating_sub(last_received) as f64 / dt;
+            self.speed = if self.speed == 0.0 {
+                rate
+            } else {
+                let alpha = 1.0 - (-dt / SPEED_TIME_CONSTANT).exp();
+                self.speed + alpha * (rate - self.speed)
+            };
+        }
+
+        self.last_sample = Some((now, received));
+    }
+
+    pub fn speed(&self) -> u64 {
+        self.speed.round() as u64
+    }
+
+    pub fn active_duration(&self) -> Duration {
+        self.active_duration + self.active_since.map(|s| s.elapsed()).unwrap_or_default()
+    }
+
+    // 总大小未知或尚无速度时无法估算
+    pub fn eta(&self, received: u64, total: u64) -> Option<u64> {
+        if total == 0 || self.speed < 1.0 {
+            return None;
+        }
+
+        let remaining = total.saturating_sub(received) as f64;
+        Some((remaining / self.speed * 1000.0).round() as u64)
+    }
+}
@@ -0,0 +1,184 @@
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use super::{temp_path_for, DownloadRecord, DownloadStatus, DownloadStore, TEMP_SUFFIX};
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReconcileReport {
+    pub checked: usize,
+    pub missing: Vec<String>,
+    pub relocated: Vec<String>,
+    pub restored: Vec<String>,
+    pub removed_parts: Vec<String>,
+}
+
+pub fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+pub fn reconcile(
+    store: &DownloadStore,
+    download_dirs: &[PathBuf],
+    active_temp_paths: &HashSet<PathBuf>,
+) -> Result<ReconcileReport, String> {
+    let records = store.list()?;
+    let mut report = ReconcileReport {
+        checked: records.len(),
+        ..Default::default()
+    };
+
+    let known_paths: HashSet<PathBuf> = records.iter().map(|r| PathBuf::from(&r.path)).collect();
+    let mut claimed: HashSet<PathBuf> = HashSet::new();
+    let untracked: Vec<(PathBuf, u64)> = download_dirs
+        .iter()
+        .flat_map(|dir| untracked_files(dir, &known_paths))
+        .collect();
+
+    // 失败或取消的下载保留 .part 文件供重试续传
+    let mut kept_parts = active_temp_paths.clone();
+
+    for record in records {
+        let path = PathBuf::from(&record.path);
+
+        if record.status != DownloadStatus::Completed {
+            let temp_path = temp_path_for(&path);
+            migrate_legacy_part(&path, &temp_path);
+            kept_parts.insert(temp_path);
+            continue;
+        }
+
+        if path.is_file() {
+            if record.missing {
+                store.update(&record.id, |r| {
+                    r.missing = false;
+                    Ok(())
+                })?;
+                report.restored.push(record.id);
+            }
+            continue;
+        }
+
+        if let Some(new_path) = find_relocated(&record, &untracked, &claimed) {
+            let filename = new_path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            store.update(&record.id, |r| {
+                r.path = new_path.to_string_lossy().to_string();
+                r.filename = filename;
+                r.missing = false;
+                Ok(())
+            })?;
+            claimed.insert(new_path);
+            report.relocated.push(record.id);
+            continue;
+        }
+
+        if !record.missing {
+            store.update(&record.id, |r| {
+                r.missing = true;
+                Ok(())
+            })?;
+        }
+        report.missing.push(record.id);
+    }
+
+    report.removed_parts = download_dirs
+        .iter()
+        .flat_map(|dir| remove_stale_parts(dir, &kept_parts))
+        .collect();
+
+    Ok(report)
+}
+
+fn untracked_files(dir: &Path, known_paths: &HashSet<PathBuf>) -> Vec<(PathBuf, u64)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            let metadata = entry.metadata().ok()?;
+            if !metadata.is_file() || known_paths.contains(&path) {
+                return None;
+            }
+            // 未完成的下载，包括其他程序的
+            if path.extension().and_then(|e| e.to_str()) == Some("part") {
+                return None;
+            }
+            Some((path, metadata.len()))
+        })
+        .collect()
+}
+
+// 只有记录了哈希的下载才能可靠地识别被外部改名的文件，先按大小筛选再计算哈希
+fn find_relocated(
+    record: &DownloadRecord,
+    untracked: &[(PathBuf, u64)],
+    claimed: &HashSet<PathBuf>,
+) -> Option<PathBuf> {
+    let expected_hash = record.sha256.as_deref()?;
+
+    untracked
+        .iter()
+        .filter(|(path, size)| *size == record.size && !claimed.contains(path))
+        .find(|(path, _)| {
+            hash_file(path)
+                .map(|hash| hash == expected_hash)
+                .unwrap_or(false)
+        })
+        .map(|(path, _)| path.clone())
+}
+
+// 旧版本使用通用的 .part 后缀，改名后重试仍可续传；旧后缀的文件不再自动清理
+fn migrate_legacy_part(path: &Path, temp_path: &Path) {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    let legacy = path.with_file_name(name);
+
+    if legacy.is_file() && !temp_path.exists() {
+        if let Err(e) = std::fs::rename(&legacy, temp_path) {
+            log::warn!("Failed to migrate {}: {}", legacy.display(), e);
+        }
+    }
+}
+
+fn remove_stale_parts(dir: &Path, kept_parts: &HashSet<PathBuf>) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut removed = Vec::new();
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        // 只清理 zher 自己的临时文件，其他程序的 .part 不属于我们
+        let is_ours = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().ends_with(TEMP_SUFFIX));
+        if !is_ours || kept_parts.contains(&path) {
+            continue;
+        }
+
+        if std::fs::remove_file(&path).is_ok() {
+            removed.push(path.to_string_lossy().to_string());
+        }
+    }
+    removed
+}
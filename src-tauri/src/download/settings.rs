@@ -0,0 +1,202 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tauri::AppHandle;
+use tauri_plugin_store::StoreExt;
+
+use super::filename::{sanitize_filename, ConflictPolicy};
+use super::mime;
+use super::progress::DEFAULT_PROGRESS_INTERVAL;
+
+const SETTINGS_STORE: &str = "download-settings.json";
+const SETTINGS_KEY: &str = "download";
+
+// 低于该值时认为目录不可用，避免刚开始下载就写满存储
+pub const MIN_FREE_SPACE: u64 = 16 * 1024 * 1024;
+
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 15;
+const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_MAX_RETRIES: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+    Category,
+    Sender,
+    Server,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DownloadSettings {
+    // 为空时使用系统默认下载目录
+    pub download_dir: Option<String>,
+    pub conflict_policy: ConflictPolicy,
+    // 例如 "zher"，配合 group_by = [category] 得到 zher/Images/
+    pub subfolder: Option<String>,
+    pub group_by: Vec<GroupBy>,
+    // zher 下载可占用的总空间上限（字节），为空表示不限制
+    pub max_total_bytes: Option<u64>,
+    // 全局限速（字节/秒），为空表示不限速
+    pub speed_limit: Option<u64>,
+    // 进度事件的最小间隔（毫秒），为空时使用默认值
+    pub progress_interval_ms: Option<u64>,
+    // 网络超时（秒），为空时使用默认值；total_timeout_secs 为空表示不限制整个下载的传输时长（暂停和重试等待不计入）
+    pub connect_timeout_secs: Option<u64>,
+    pub idle_timeout_secs: Option<u64>,
+    pub total_timeout_secs: Option<u64>,
+    // 网络中断或停滞后自动续传的次数
+    pub max_retries: Option<u32>,
+    // 设置后通过 X-Zher-Client 请求头发送，便于服务器区分设备
+    pub client_id: Option<String>,
+    // 下载完成后自动解压 zip、tar、tar.gz 到同名文件夹
+    pub extract_archives: bool,
+    // 下载完成后用默认应用打开
+    pub auto_open: bool,
+    // 仅桌面端：下载完成后执行的命令及参数，参数中的 {path}、{name}、{dir} 会被替换，为空表示不执行
+    pub post_download_command: Vec<String>,
+    // 为图片和音视频生成缩略图并读取尺寸、拍摄时间、时长，为空时默认开启
+    pub thumbnails: Option<bool>,
+}
+
+pub fn load(app: &AppHandle) -> DownloadSettings {
+    let store = match app.store(SETTINGS_STORE) {
+        Ok(store) => store,
+        Err(e) => {
+            log::error!("Failed to open download settings: {}", e);
+            return DownloadSettings::default();
+        }
+    };
+
+    store
+        .get(SETTINGS_KEY)
+        .and_then(|value| serde_json::from_value(value).ok())
+        .unwrap_or_default()
+}
+
+pub fn save(app: &AppHandle, settings: &DownloadSettings) -> Result<(), String> {
+    let store = app.store(SETTINGS_STORE).map_err(|e| e.to_string())?;
+    let value = serde_json::to_value(settings).map_err(|e| e.to_string())?;
+    store.set(SETTINGS_KEY, value);
+    store.save().map_err(|e| e.to_string())
+}
+
+pub fn default_download_root(app: &AppHandle) -> Result<PathBuf, String> {
+    #[cfg(target_os = "android")]
+    {
+        let _ = app;
+        Ok(PathBuf::from("/storage/emulated/0/Download"))
+    }
+
+    #[cfg(not(target_os = "android"))]
+    {
+        use tauri::Manager;
+        app.path().download_dir().map_err(|e| e.to_string())
+    }
+}
+
+impl DownloadSettings {
+    pub fn progress_interval(&self) -> Duration {
+        self.progress_interval_ms
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_PROGRESS_INTERVAL)
+    }
+
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(
+            self.connect_timeout_secs
+                .filter(|s| *s > 0)
+                .unwrap_or(DEFAULT_CONNECT_TIMEOUT_SECS),
+        )
+    }
+
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(
+            self.idle_timeout_secs
+                .filter(|s| *s > 0)
+                .unwrap_or(DEFAULT_IDLE_TIMEOUT_SECS),
+        )
+    }
+
+    pub fn total_timeout(&self) -> Option<Duration> {
+        self.total_timeout_secs
+            .filter(|s| *s > 0)
+            .map(Duration::from_secs)
+    }
+
+    pub fn max_retries(&self) -> u32 {
+        self.max_retries.unwrap_or(DEFAULT_MAX_RETRIES)
+    }
+
+    pub fn thumbnails(&self) -> bool {
+        self.thumbnails.unwrap_or(true)
+    }
+
+    pub fn download_root(&self, app: &AppHandle) -> Result<PathBuf, String> {
+        match self.download_dir.as_deref().map(str::trim) {
+            Some(dir) if !dir.is_empty() => Ok(PathBuf::from(dir)),
+            _ => default_download_root(app),
+        }
+    }
+
+    // 根据规则计算文件最终所在目录，每一级都经过文件名清洗，不会逃出下载根目录；
+    // mime_type 用于按类别分组，下载开始前只能按文件名推断，完成后使用按内容确定的类型
+    pub fn target_dir(
+        &self,
+        root: &Path,
+        mime_type: &str,
+        sender_name: Option<&str>,
+        url: &str,
+    ) -> PathBuf {
+        let mut dir = root.to_path_buf();
+
+        if let Some(subfolder) = self.subfolder.as_deref().filter(|s| !s.trim().is_empty()) {
+            dir.push(sanitize_filename(subfolder));
+        }
+
+        for group in &self.group_by {
+            let component = match group {
+                GroupBy::Category => Some(mime::category(mime_type).to_string()),
+                GroupBy::Sender => sender_name.filter(|s| !s.trim().is_empty()).map(str::to_string),
+                GroupBy::Server => url::Url::parse(url).ok().and_then(|u| {
+                    u.host_str().map(|host| match u.port() {
+                        Some(port) => format!("{}_{}", host, port),
+                        None => host.to_string(),
+                    })
+                }),
+            };
+
+            if let Some(component) = component {
+                dir.push(sanitize_filename(&component));
+            }
+        }
+
+        dir
+    }
+}
+
+// 创建目录并确认可写、剩余空间足够
+pub fn validate_dir(dir: &Path) -> Result<(), String> {
+    std::fs::create_dir_all(dir)
+        .map_err(|e| format!("Failed to create download dir: {}", e))?;
+
+    let probe = dir.join(".zher-write-test");
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&probe)
+        .map_err(|e| format!("Download dir is not writable: {}", e))?;
+    let _ = std::fs::remove_file(&probe);
+
+    let available = fs4::available_space(dir)
+        .map_err(|e| format!("Failed to query free space: {}", e))?;
+    if available < MIN_FREE_SPACE {
+        return Err(format!(
+            "Not enough free space in download dir ({} bytes available)",
+            available
+        ));
+    }
+
+    Ok(())
+}
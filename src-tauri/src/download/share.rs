@@ -0,0 +1,163 @@
+use futures_util::stream;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tauri::{AppHandle, Emitter};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use super::client::HttpClient;
+use super::progress::DEFAULT_PROGRESS_INTERVAL;
+use super::throttle::SpeedLimiter;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// 向 zher 服务器发布的文件，接收方请求时服务器通过 start-upload 通知上传；记录删除时一并移除
+#[derive(Debug, Clone)]
+pub struct SharedFile {
+    pub record_id: String,
+    pub path: PathBuf,
+    pub server: String,
+}
+
+// 与 zher 网页端发送的 file-meta 消息格式一致，前端原样转发
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShareOffer {
+    pub file_id: String,
+    pub file_name: String,
+    pub file_size: u64,
+    pub file_type: String,
+}
+
+pub type SharedFiles = Arc<Mutex<HashMap<String, SharedFile>>>;
+
+struct UploadStream {
+    file: tokio::fs::File,
+    remaining: u64,
+    sent: u64,
+    total: u64,
+    limiter: SpeedLimiter,
+    app: AppHandle,
+    file_id: String,
+    transfer_id: String,
+    last_emit: Instant,
+}
+
+impl UploadStream {
+    fn emit_progress(&mut self) {
+        self.last_emit = Instant::now();
+        self.app
+            .emit(
+                "share-progress",
+                serde_json::json!({
+                    "fileId": self.file_id,
+                    "transferId": self.transfer_id,
+                    "sent": self.sent,
+                    "total": self.total,
+                }),
+            )
+            .unwrap_or(());
+    }
+}
+
+// 上传 [offset, end] 区间（end 为空表示到文件末尾），与网页端处理 start-upload 的方式相同
+#[allow(clippy::too_many_arguments)]
+pub async fn upload(
+    app: &AppHandle,
+    http: &HttpClient,
+    limiter: SpeedLimiter,
+    shared: &SharedFile,
+    file_id: &str,
+    transfer_id: &str,
+    offset: u64,
+    end: Option<u64>,
+) -> Result<u64, String> {
+    let mut file = tokio::fs::File::open(&shared.path)
+        .await
+        .map_err(|e| format!("Failed to open file: {}", e))?;
+    let size = file.metadata().await.map_err(|e| e.to_string())?.len();
+
+    let stop = end.map_or(size, |end| end.saturating_add(1).min(size));
+    if offset > stop {
+        return Err("Invalid range".to_string());
+    }
+    let total = stop - offset;
+    file.seek(std::io::SeekFrom::Start(offset))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let state = UploadStream {
+        file,
+        remaining: total,
+        sent: 0,
+        total,
+        limiter,
+        app: app.clone(),
+        file_id: file_id.to_string(),
+        transfer_id: transfer_id.to_string(),
+        last_emit: Instant::now(),
+    };
+
+    let body = stream::unfold(state, |mut state| async move {
+        if state.remaining == 0 {
+            state.emit_progress();
+            return None;
+        }
+
+        let mut buf = vec![0; CHUNK_SIZE.min(state.remaining as usize)];
+        let read = match state.file.read(&mut buf).await {
+            Ok(0) => {
+                let err = std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "File truncated");
+                return Some((Err(err), state));
+            }
+            Ok(n) => n,
+            Err(e) => return Some((Err(e), state)),
+        };
+        buf.truncate(read);
+
+        let delay = state.limiter.consume(read as u64);
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+
+        state.remaining -= read as u64;
+        state.sent += read as u64;
+        if state.last_emit.elapsed() >= DEFAULT_PROGRESS_INTERVAL {
+            state.emit_progress();
+        }
+
+        Some((Ok(buf), state))
+    });
+
+    let url = format!("{}/api/upload/{}", shared.server.trim_end_matches('/'), transfer_id);
+    let response = http
+        .request(reqwest::Method::POST, &url)
+        .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+        .header(reqwest::header::CONTENT_LENGTH, total)
+        .body(reqwest::Body::wrap_stream(body))
+        .send()
+        .await
+        .map_err(|e| format!("Upload failed: {}", e))?;
+
+    if !response.status().is_success() {
+        return Err(format!("Upload failed: HTTP {}", response.status()));
+    }
+
+    Ok(total)
+}
+
+pub fn offer(file_id: String, path: &Path, file_name: &str, mime_type: String) -> Result<ShareOffer, String> {
+    let metadata = std::fs::metadata(path).map_err(|_| "File not found".to_string())?;
+    if !metadata.is_file() {
+        return Err("Not a file".to_string());
+    }
+
+    Ok(ShareOffer {
+        file_id,
+        file_name: file_name.to_string(),
+        file_size: metadata.len(),
+        file_type: mime_type,
+    })
+}
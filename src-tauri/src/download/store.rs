@@ -0,0 +1,449 @@
+use rusqlite::{params, Connection, ErrorCode, OptionalExtension};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use super::{DownloadRecord, DownloadStatus};
+
+// 每个元素对应一个 schema 版本，按顺序执行，已执行的版本记录在 PRAGMA user_version 中
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE downloads (
+        id TEXT PRIMARY KEY NOT NULL,
+        filename TEXT NOT NULL,
+        path TEXT NOT NULL,
+        timestamp INTEGER NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE INDEX idx_downloads_timestamp ON downloads(timestamp DESC);
+    CREATE INDEX idx_downloads_filename ON downloads(filename);",
+    "ALTER TABLE downloads ADD COLUMN idempotency_key TEXT;
+    UPDATE downloads SET idempotency_key = json_extract(data, '$.idempotency_key');
+    CREATE INDEX idx_downloads_idempotency_key ON downloads(idempotency_key);",
+    "CREATE INDEX idx_downloads_path ON downloads(path);",
+];
+
+// 只有文件本身损坏时才应当移走并从备份恢复；版本过新、被占用、没有权限等情况下文件仍然完好
+#[derive(Debug)]
+pub enum OpenError {
+    Corrupt(String),
+    Other(String),
+}
+
+impl std::fmt::Display for OpenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OpenError::Corrupt(e) | OpenError::Other(e) => f.write_str(e),
+        }
+    }
+}
+
+fn open_error(e: rusqlite::Error) -> OpenError {
+    let message = format!("Failed to open download database: {}", e);
+    match e.sqlite_error_code() {
+        Some(ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase) => OpenError::Corrupt(message),
+        _ => OpenError::Other(message),
+    }
+}
+
+#[derive(Clone)]
+pub struct DownloadStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl DownloadStore {
+    pub fn open(path: &Path) -> Result<Self, OpenError> {
+        let conn = Connection::open(path).map_err(open_error)?;
+        Self::init(conn)
+    }
+
+    // 数据库文件暂时无法使用时的退路，本次运行的记录不会保存
+    pub fn open_in_memory() -> Result<Self, String> {
+        let conn = Connection::open_in_memory().map_err(|e| e.to_string())?;
+        Self::init(conn).map_err(|e| e.to_string())
+    }
+
+    fn init(mut conn: Connection) -> Result<Self, OpenError> {
+        conn.execute_batch("PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL;")
+            .map_err(open_error)?;
+
+        let integrity: String = conn
+            .query_row("PRAGMA quick_check", [], |row| row.get(0))
+            .map_err(open_error)?;
+        if integrity != "ok" {
+            return Err(OpenError::Corrupt(format!(
+                "Download database is corrupted: {}",
+                integrity
+            )));
+        }
+
+        migrate(&mut conn).map_err(OpenError::Other)?;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // 写入临时文件并 fsync 后再重命名，保证备份文件始终是完整的一代
+    pub fn backup_to(&self, path: &Path) -> Result<(), String> {
+        let temp_path = path.with_extension("tmp");
+        let _ = std::fs::remove_file(&temp_path);
+
+        {
+            let conn = self.lock();
+            conn.execute(
+                "VACUUM INTO ?1",
+                params![temp_path.to_string_lossy().to_string()],
+            )
+            .map_err(|e| format!("Failed to back up download database: {}", e))?;
+        }
+
+        std::fs::File::open(&temp_path)
+            .and_then(|f| f.sync_all())
+            .map_err(|e| e.to_string())?;
+        std::fs::rename(&temp_path, path).map_err(|e| e.to_string())?;
+
+        Ok(())
+    }
+
+    // 从旧版 downloads.json 导入记录，主文件损坏时尝试 .bak，导入成功后重命名旧文件避免重复导入
+    pub fn import_legacy_json(&self, json_path: &Path) -> Result<usize, String> {
+        if !json_path.exists() {
+            return Ok(0);
+        }
+
+        let records = match read_legacy_json(json_path) {
+            Ok(records) => records,
+            Err(e) => {
+                log::warn!("{}, trying backup", e);
+                let backup = read_legacy_json(&json_path.with_extension("json.bak"));
+                if backup.is_err() {
+                    // 保留损坏的文件以便手动恢复，而不是当作空列表覆盖
+                    let _ = std::fs::rename(json_path, json_path.with_extension("json.corrupt"));
+                }
+                backup.map_err(|_| e)?
+            }
+        };
+
+        let mut conn = self.lock();
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        for record in &records {
+            // 已存在的记录以数据库为准
+            insert_record(&tx, record, false)?;
+        }
+        tx.commit().map_err(|e| e.to_string())?;
+        drop(conn);
+
+        std::fs::rename(json_path, json_path.with_extension("json.migrated"))
+            .map_err(|e| e.to_string())?;
+
+        Ok(records.len())
+    }
+
+    pub fn list(&self) -> Result<Vec<DownloadRecord>, String> {
+        let conn = self.lock();
+        list_records(&conn)
+    }
+
+    pub fn get(&self, id: &str) -> Result<Option<DownloadRecord>, String> {
+        let conn = self.lock();
+        get_record(&conn, id)
+    }
+
+    pub fn is_filename_taken(&self, filename: &str) -> Result<bool, String> {
+        let conn = self.lock();
+        conn.query_row(
+            "SELECT 1 FROM downloads WHERE filename = ?1 LIMIT 1",
+            params![filename],
+            |_| Ok(()),
+        )
+        .optional()
+        .map(|r| r.is_some())
+        .map_err(|e| e.to_string())
+    }
+
+    pub fn find_by_idempotency_key(&self, key: &str) -> Result<Option<DownloadRecord>, String> {
+        let conn = self.lock();
+        let data: Option<String> = conn
+            .query_row(
+                "SELECT data FROM downloads WHERE idempotency_key = ?1
+                 ORDER BY timestamp DESC LIMIT 1",
+                params![key],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?;
+
+        match data {
+            Some(data) => serde_json::from_str(&data)
+                .map(Some)
+                .map_err(|e| e.to_string()),
+            None => Ok(None),
+        }
+    }
+
+    pub fn find_by_path(&self, path: &str) -> Result<Option<DownloadRecord>, String> {
+        let conn = self.lock();
+        let data: Option<String> = conn
+            .query_row(
+                "SELECT data FROM downloads WHERE path = ?1
+                 ORDER BY timestamp DESC LIMIT 1",
+                params![path],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?;
+
+        match data {
+            Some(data) => serde_json::from_str(&data)
+                .map(Some)
+                .map_err(|e| e.to_string()),
+            None => Ok(None),
+        }
+    }
+
+    pub fn max_task_id(&self) -> Result<u64, String> {
+        let conn = self.lock();
+        let max: Option<i64> = conn
+            .query_row(
+                "SELECT MAX(CAST(id AS INTEGER)) FROM downloads",
+                [],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(max.unwrap_or(0).max(0) as u64)
+    }
+
+    // 已完成且文件仍存在的下载所占用的总字节数
+    pub fn total_size(&self) -> Result<u64, String> {
+        let conn = self.lock();
+        let total: i64 = conn
+            .query_row(
+                "SELECT COALESCE(SUM(json_extract(data, '$.size')), 0) FROM downloads
+                 WHERE COALESCE(json_extract(data, '$.status'), 'completed') = 'completed'
+                   AND COALESCE(json_extract(data, '$.missing'), 0) = 0",
+                [],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(total.max(0) as u64)
+    }
+
+    pub fn upsert(&self, record: &DownloadRecord) -> Result<(), String> {
+        let conn = self.lock();
+        insert_record(&conn, record, true)
+    }
+
+    // 在同一事务中读取、修改并写回单条记录，闭包返回 Err 时回滚
+    pub fn update<T, F>(&self, id: &str, f: F) -> Result<T, String>
+    where
+        F: FnOnce(&mut DownloadRecord) -> Result<T, String>,
+    {
+        let mut conn = self.lock();
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+        let mut record = get_record(&tx, id)?.ok_or("Download not found")?;
+        let result = f(&mut record)?;
+        insert_record(&tx, &record, true)?;
+
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(result)
+    }
+
+    pub fn remove(&self, id: &str) -> Result<Option<DownloadRecord>, String> {
+        let mut conn = self.lock();
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+        let record = get_record(&tx, id)?;
+        if record.is_some() {
+            tx.execute("DELETE FROM downloads WHERE id = ?1", params![id])
+                .map_err(|e| e.to_string())?;
+        }
+
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(record)
+    }
+
+    pub fn remove_other_with_path(&self, path: &str, keep_id: &str) -> Result<usize, String> {
+        let conn = self.lock();
+        conn.execute(
+            "DELETE FROM downloads WHERE path = ?1 AND id != ?2",
+            params![path, keep_id],
+        )
+        .map_err(|e| e.to_string())
+    }
+
+    // 在同一事务中删除所有匹配的记录，ids 和 status 都为空时删除全部
+    pub fn remove_matching(
+        &self,
+        ids: Option<&[String]>,
+        status: Option<DownloadStatus>,
+    ) -> Result<Vec<DownloadRecord>, String> {
+        let mut conn = self.lock();
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+        let records: Vec<DownloadRecord> = list_records(&tx)?
+            .into_iter()
+            .filter(|r| ids.map_or(true, |ids| ids.contains(&r.id)))
+            .filter(|r| status.map_or(true, |status| r.status == status))
+            .collect();
+
+        for record in &records {
+            tx.execute("DELETE FROM downloads WHERE id = ?1", params![record.id])
+                .map_err(|e| e.to_string())?;
+        }
+
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(records)
+    }
+
+    pub fn clear(&self) -> Result<Vec<DownloadRecord>, String> {
+        let mut conn = self.lock();
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+        let records = list_records(&tx)?;
+        tx.execute("DELETE FROM downloads", [])
+            .map_err(|e| e.to_string())?;
+
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(records)
+    }
+}
+
+fn read_legacy_json(path: &Path) -> Result<Vec<DownloadRecord>, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    serde_json::from_str(&content).map_err(|e| format!("Invalid {}: {}", path.display(), e))
+}
+
+fn migrate(conn: &mut Connection) -> Result<(), String> {
+    let version: i64 = conn
+        .query_row("PRAGMA user_version", [], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+
+    if version as usize > MIGRATIONS.len() {
+        return Err(format!(
+            "Download database schema version {} is newer than supported version {}",
+            version,
+            MIGRATIONS.len()
+        ));
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        tx.execute_batch(migration)
+            .map_err(|e| format!("Migration {} failed: {}", index + 1, e))?;
+        tx.pragma_update(None, "user_version", (index + 1) as i64)
+            .map_err(|e| e.to_string())?;
+        tx.commit().map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+fn list_records(conn: &Connection) -> Result<Vec<DownloadRecord>, String> {
+    let mut stmt = conn
+        .prepare("SELECT data FROM downloads ORDER BY timestamp DESC")
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map([], |row| row.get::<_, String>(0))
+        .map_err(|e| e.to_string())?;
+
+    let mut records = Vec::new();
+    for data in rows {
+        let data = data.map_err(|e| e.to_string())?;
+        match serde_json::from_str(&data) {
+            Ok(record) => records.push(record),
+            Err(e) => log::warn!("Skipping unreadable download record: {}", e),
+        }
+    }
+    Ok(records)
+}
+
+fn get_record(conn: &Connection, id: &str) -> Result<Option<DownloadRecord>, String> {
+    let data: Option<String> = conn
+        .query_row(
+            "SELECT data FROM downloads WHERE id = ?1",
+            params![id],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+
+    match data {
+        Some(data) => serde_json::from_str(&data)
+            .map(Some)
+            .map_err(|e| e.to_string()),
+        None => Ok(None),
+    }
+}
+
+fn insert_record(conn: &Connection, record: &DownloadRecord, replace: bool) -> Result<(), String> {
+    let data = serde_json::to_string(record).map_err(|e| e.to_string())?;
+    let sql = if replace {
+        "INSERT INTO downloads (id, filename, path, timestamp, idempotency_key, data)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+         ON CONFLICT(id) DO UPDATE SET
+            filename = excluded.filename,
+            path = excluded.path,
+            timestamp = excluded.timestamp,
+            idempotency_key = excluded.idempotency_key,
+            data = excluded.data"
+    } else {
+        "INSERT OR IGNORE INTO downloads (id, filename, path, timestamp, idempotency_key, data)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)"
+    };
+
+    conn.execute(
+        sql,
+        params![
+            record.id,
+            record.filename,
+            record.path,
+            record.timestamp,
+            record.idempotency_key,
+            data
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_db(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("zher-store-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn garbage_file_is_corrupt() {
+        let path = temp_db("garbage.db");
+        std::fs::write(&path, vec![0x42u8; 8192]).unwrap();
+        assert!(matches!(DownloadStore::open(&path), Err(OpenError::Corrupt(_))));
+    }
+
+    #[test]
+    fn newer_schema_is_not_corrupt() {
+        let path = temp_db("newer.db");
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.pragma_update(None, "user_version", 999).unwrap();
+        }
+        assert!(matches!(DownloadStore::open(&path), Err(OpenError::Other(_))));
+    }
+
+    #[test]
+    fn opens_new_database() {
+        let path = temp_db("new.db");
+        assert!(DownloadStore::open(&path).is_ok());
+    }
+}
@@ -0,0 +1,76 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// 令牌桶容量至少为这么多字节，小文件可以一次性通过而不被限速拖慢
+const MIN_BURST: u64 = 256 * 1024;
+
+pub struct TokenBucket {
+    // 字节/秒，0 表示不限速
+    rate: u64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: Option<u64>) -> Self {
+        let mut bucket = Self {
+            rate: 0,
+            tokens: 0.0,
+            last_refill: Instant::now(),
+        };
+        bucket.set_rate(rate);
+        bucket
+    }
+
+    pub fn set_rate(&mut self, rate: Option<u64>) {
+        self.rate = rate.unwrap_or(0);
+        self.tokens = self.capacity();
+        self.last_refill = Instant::now();
+    }
+
+    fn capacity(&self) -> f64 {
+        self.rate.max(MIN_BURST) as f64
+    }
+
+    // 扣除 amount 个令牌，返回需要等待多久才能继续；允许欠账，由等待时间偿还
+    pub fn consume(&mut self, amount: u64) -> Duration {
+        if self.rate == 0 {
+            return Duration::ZERO;
+        }
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.capacity());
+        self.tokens -= amount as f64;
+
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate as f64)
+        }
+    }
+}
+
+// 所有传输共享的全局限速器
+#[derive(Clone)]
+pub struct SpeedLimiter(Arc<Mutex<TokenBucket>>);
+
+impl SpeedLimiter {
+    pub fn new(rate: Option<u64>) -> Self {
+        Self(Arc::new(Mutex::new(TokenBucket::new(rate))))
+    }
+
+    pub fn set_rate(&self, rate: Option<u64>) {
+        if let Ok(mut bucket) = self.0.lock() {
+            bucket.set_rate(rate);
+        }
+    }
+
+    pub fn consume(&self, amount: u64) -> Duration {
+        self.0
+            .lock()
+            .map(|mut bucket| bucket.consume(amount))
+            .unwrap_or(Duration::ZERO)
+    }
+}
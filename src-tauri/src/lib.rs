@@ -3,10 +3,7 @@ mod download;
 mod server;
 mod shared_files;
 
-use std::collections::HashMap;
-use std::sync::Arc;
 use tauri::Manager;
-use tokio::sync::Mutex;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
@@ -31,19 +28,23 @@ pub fn run() {
                 )?;
             }
 
-            let download_state = download::DownloadState {
-                records: Arc::new(Mutex::new(Vec::<download::DownloadRecord>::new())),
-                active_downloads: Arc::new(Mutex::new(
-                    HashMap::<u64, download::ActiveDownload>::new(),
-                )),
-            };
+            let download_state = download::DownloadState::new(
+                download::init_store(app.handle())?,
+                download::load_settings(app.handle()),
+            )?;
 
             let app_handle = app.handle().clone();
+            let startup_state = download_state.clone();
             tauri::async_runtime::spawn(async move {
-                let records = download::load_downloads(&app_handle).await;
-                if let Some(state) = app_handle.try_state::<download::DownloadState>() {
-                    let mut locked = state.records.lock().await;
-                    *locked = records;
+                match download::reconcile_with_filesystem(&app_handle, &startup_state).await {
+                    Ok(report) => log::info!(
+                        "Reconciled {} downloads: {} missing, {} relocated, {} stale parts removed",
+                        report.checked,
+                        report.missing.len(),
+                        report.relocated.len(),
+                        report.removed_parts.len()
+                    ),
+                    Err(e) => log::error!("Failed to reconcile downloads: {}", e),
                 }
             });
 
@@ -54,16 +55,35 @@ pub fn run() {
         })
         .invoke_handler(tauri::generate_handler![
             download::download_file,
+            download::resolve_download_conflict,
+            download::retry_download,
+            download::redownload,
             download::pause_download,
             download::resume_download,
             download::cancel_download,
+            download::pause_downloads,
+            download::resume_downloads,
+            download::cancel_downloads,
+            download::set_download_speed_limit,
             download::get_downloads,
+            download::get_active_downloads,
+            download::reconcile_downloads,
+            download::get_download_settings,
+            download::set_download_settings,
+            download::set_server_credentials,
+            download::get_download_dir,
             download::delete_download,
             download::delete_all_downloads,
+            download::delete_downloads,
             download::open_download_file,
             download::rename_download,
+            download::extract_download,
+            download::get_download_thumbnail,
             download::share_download,
+            download::upload_shared_file,
             download::open_with_download,
+            download::reveal_in_folder,
+            download::get_open_with_apps,
             discovery::discover_services,
             discovery::validate_service_url,
             discovery::get_local_ip,
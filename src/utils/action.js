@@ -16,7 +16,7 @@ const autoAction = async (msg, serverUrl) => {
         await copyText(msg.text);
     }
     if (msg.type === 'file-meta' && autoDownload.value) {
-        await startDownload(msg.fileId, msg.fileName, serverUrl);
+        await startDownload(msg.fileId, msg.fileName, serverUrl, msg);
     }
 }
 
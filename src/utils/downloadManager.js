@@ -6,7 +6,53 @@ export const activeDownloads = ref(new Map());
 
 const taskIdToFileId = new Map();
 
+// 聊天消息按 fileId 查找下载进度，重新加载后从下载地址中还原
+function fileIdFromUrl(url, fallback) {
+    const match = /\/api\/download\/([^/?#]+)/.exec(url);
+    return match ? decodeURIComponent(match[1]) : fallback;
+}
+
+async function restoreActiveDownloads() {
+    try {
+        const snapshots = await invoke('get_active_downloads');
+        const newMap = new Map(activeDownloads.value);
+
+        for (const snapshot of snapshots) {
+            const taskIdStr = String(snapshot.id);
+            const fileId = fileIdFromUrl(snapshot.url, snapshot.record_id);
+            taskIdToFileId.set(taskIdStr, fileId);
+            newMap.set(fileId, {
+                taskId: taskIdStr,
+                name: snapshot.filename,
+                size: snapshot.total,
+                received: snapshot.received,
+                speed: snapshot.speed,
+                eta: snapshot.eta,
+                elapsed: 0,
+                progress: snapshot.total > 0 ? Math.round((snapshot.received / snapshot.total) * 100) : 0,
+                status: snapshot.state === 'paused' ? 'paused' : 'downloading'
+            });
+        }
+
+        activeDownloads.value = newMap;
+    } catch (err) {
+        console.error('Restore active downloads failed:', err);
+    }
+}
+
 export async function initDownloadManager() {
+    await listen('download-state-changed', (event) => {
+        const { id, state, error } = event.payload;
+        const fileId = taskIdToFileId.get(String(id));
+        const download = fileId && activeDownloads.value.get(fileId);
+
+        if (download && (state === 'paused' || state === 'downloading' || state === 'pending')) {
+            const newMap = new Map(activeDownloads.value);
+            newMap.set(fileId, { ...download, status: state, error });
+            activeDownloads.value = newMap;
+        }
+    });
+
     await listen('download-started', (event) => {
         const { id, name, size } = event.payload;
         const taskIdStr = String(id);
@@ -19,6 +65,9 @@ export async function initDownloadManager() {
                 name,
                 size,
                 received: 0,
+                speed: 0,
+                eta: null,
+                elapsed: 0,
                 progress: 0,
                 status: 'downloading'
             });
@@ -27,7 +76,7 @@ export async function initDownloadManager() {
     });
 
     await listen('download-progress', (event) => {
-        const { id, received, total } = event.payload;
+        const { id, received, total, speed, eta, elapsed } = event.payload;
         const taskIdStr = String(id);
         const fileId = taskIdToFileId.get(taskIdStr);
         
@@ -38,6 +87,10 @@ export async function initDownloadManager() {
                 newMap.set(fileId, {
                     ...download,
                     received,
+                    size: total,
+                    speed,
+                    eta,
+                    elapsed,
                     progress: total > 0 ? Math.round((received / total) * 100) : 0
                 });
                 activeDownloads.value = newMap;
@@ -132,13 +185,48 @@ export async function initDownloadManager() {
             }
         }
     });
+
+    await listen('download-cancelled', (event) => {
+        const taskIdStr = String(event.payload.id);
+        const fileId = taskIdToFileId.get(taskIdStr);
+
+        if (fileId) {
+            const newMap = new Map(activeDownloads.value);
+            newMap.delete(fileId);
+            activeDownloads.value = newMap;
+            taskIdToFileId.delete(taskIdStr);
+        }
+    });
+
+    // 冲突策略为“询问”时，后端等待这里的选择后再继续下载
+    await listen('download-conflict', async (event) => {
+        const { id, filename, suggested } = event.payload;
+        let policy = 'skip';
+        if (confirm(`“${filename}”已存在，是否仍要下载？`)) {
+            policy = confirm(`是否覆盖原文件？选择取消将保存为“${suggested}”`) ? 'overwrite' : 'rename';
+        }
+
+        try {
+            await invoke('resolve_download_conflict', { id, policy });
+        } catch (err) {
+            console.error('Resolve download conflict failed:', err);
+        }
+    });
+
+    await restoreActiveDownloads();
 }
 
-export async function startDownload (fileId, fileName, serverUrl) {
+export async function startDownload (fileId, fileName, serverUrl, sender = {}) {
     try {
         const baseUrl = serverUrl.endsWith('/') ? serverUrl.slice(0, -1) : serverUrl;
         const url = `${baseUrl}/api/download/${fileId}`;
-        const taskId = await invoke('download_file', { url, filename: fileName });
+        const taskId = await invoke('download_file', {
+            url,
+            filename: fileName,
+            senderName: sender.senderName ?? null,
+            senderId: sender.senderId ?? null,
+            idempotencyKey: url
+        });
         
         if (taskId && fileId) {
             const taskIdStr = String(taskId);
@@ -152,11 +240,27 @@ export async function startDownload (fileId, fileName, serverUrl) {
     }
 };
 
+export async function retryDownload(record) {
+    try {
+        const command = record.status === 'completed' ? 'redownload' : 'retry_download';
+        const taskId = await invoke(command, { id: record.id });
+
+        if (taskId) {
+            taskIdToFileId.set(String(taskId), record.id);
+        }
+
+        return taskId ? String(taskId) : null;
+    } catch (err) {
+        console.error('Retry download failed:', err);
+        return null;
+    }
+}
+
 export async function pauseDownload(fileId) {
     try {
         const download = activeDownloads.value.get(fileId);
         if (download && download.taskId) {
-            const taskId = typeof download.taskId === 'string' ? parseInt(download.taskId) : download.taskId;
+            const taskId = Number(download.taskId);
             await invoke('pause_download', { taskId });
         }
     } catch (err) {
@@ -168,7 +272,7 @@ export async function resumeDownload(fileId) {
     try {
         const download = activeDownloads.value.get(fileId);
         if (download && download.taskId) {
-            const taskId = typeof download.taskId === 'string' ? parseInt(download.taskId) : download.taskId;
+            const taskId = Number(download.taskId);
             await invoke('resume_download', { taskId });
         }
     } catch (err) {
@@ -180,7 +284,7 @@ export async function cancelDownload(fileId) {
     try {
         const download = activeDownloads.value.get(fileId);
         if (download && download.taskId) {
-            const taskId = typeof download.taskId === 'string' ? parseInt(download.taskId) : download.taskId;
+            const taskId = Number(download.taskId);
             await invoke('cancel_download', { taskId });
             
             const newMap = new Map(activeDownloads.value);
@@ -194,3 +298,18 @@ export async function cancelDownload(fileId) {
 }
 
 
+export async function pauseAllDownloads() {
+    try {
+        await invoke('pause_downloads', { taskIds: null });
+    } catch (err) {
+        console.error('Pause all downloads failed:', err);
+    }
+}
+
+export async function resumeAllDownloads() {
+    try {
+        await invoke('resume_downloads', { taskIds: null });
+    } catch (err) {
+        console.error('Resume all downloads failed:', err);
+    }
+}
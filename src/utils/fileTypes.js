@@ -48,11 +48,30 @@ export function getCategoryByExtension(filename) {
   return 'OTHER';
 }
 
+// 与后端 mime::category 的分类一致，无法按类型判断时返回 null
+export function getCategoryByMime(mimeType) {
+  if (!mimeType) return null;
+  const [type, subtype = ''] = mimeType.split('/');
+
+  if (type === 'image') return 'IMAGE';
+  if (type === 'video') return 'VIDEO';
+  if (type === 'audio') return 'AUDIO';
+  if (subtype === 'pdf' || /document|msword|sheet|presentation/.test(subtype)) return 'DOCUMENT';
+  if (['zip', 'gzip', 'x-tar', 'x-7z-compressed', 'vnd.rar'].includes(subtype)) return 'ARCHIVE';
+  if (subtype === 'vnd.android.package-archive') return 'APPLICATION';
+  return null;
+}
+
+// 优先使用下载完成时按内容确定的类型；旧记录和文本类文件（代码、表格等）按扩展名
+export function getCategory(download) {
+  return getCategoryByMime(download.mime_type) || getCategoryByExtension(download.filename);
+}
+
 export function getAvailableCategories(downloads) {
   const categories = new Set(['ALL']);
   
   downloads.forEach(download => {
-    const category = getCategoryByExtension(download.filename);
+    const category = getCategory(download);
     if (category !== 'OTHER') {
       categories.add(category);
     }
@@ -69,6 +88,14 @@ export function formatFileSize(bytes) {
   return parseFloat((bytes / Math.pow(k, i)).toFixed(2)) + ' ' + sizes[i];
 }
 
+export function formatDuration(ms) {
+  const seconds = Math.max(0, Math.round(ms / 1000));
+  if (seconds < 60) return seconds + ' 秒';
+  const minutes = Math.floor(seconds / 60);
+  if (minutes < 60) return minutes + ' 分 ' + (seconds % 60) + ' 秒';
+  return Math.floor(minutes / 60) + ' 小时 ' + (minutes % 60) + ' 分';
+}
+
 export function groupByDate(downloads) {
   const groups = {};
   const today = new Date();
@@ -0,0 +1,78 @@
+import { ref } from 'vue';
+import { listen } from '@tauri-apps/api/event';
+import { invoke } from '@tauri-apps/api/core';
+import { useGlobalSocket } from '../composables/useGlobalSocket';
+
+// transferId -> { fileId, name, sent, total, status }
+export const activeShares = ref(new Map());
+
+const sharedNames = new Map();
+let listening = false;
+
+const { connect, emit, registerCallback } = useGlobalSocket();
+
+function updateShare(transferId, patch) {
+    const newMap = new Map(activeShares.value);
+    newMap.set(transferId, { ...(newMap.get(transferId) || {}), ...patch });
+    activeShares.value = newMap;
+}
+
+function removeShareLater(transferId) {
+    setTimeout(() => {
+        const newMap = new Map(activeShares.value);
+        newMap.delete(transferId);
+        activeShares.value = newMap;
+    }, 3000);
+}
+
+async function initListeners() {
+    if (listening) return;
+    listening = true;
+
+    await listen('share-progress', (event) => {
+        const { fileId, transferId, sent, total } = event.payload;
+        updateShare(transferId, { fileId, name: sharedNames.get(fileId), sent, total, status: 'uploading' });
+    });
+
+    await listen('share-completed', (event) => {
+        const { fileId, transferId } = event.payload;
+        updateShare(transferId, { fileId, name: sharedNames.get(fileId), status: 'completed' });
+        removeShareLater(transferId);
+    });
+
+    await listen('share-failed', (event) => {
+        const { fileId, transferId, error } = event.payload;
+        updateShare(transferId, { fileId, name: sharedNames.get(fileId), status: 'failed', error });
+        removeShareLater(transferId);
+    });
+}
+
+// 接收方开始下载时服务器发出 start-upload，浏览器页面发送的文件由页面自己处理
+const onStartUpload = ({ fileId, transferId, offset = 0, end }) => {
+    if (!sharedNames.has(fileId)) return;
+
+    invoke('upload_shared_file', {
+        fileId,
+        transferId,
+        offset,
+        end: typeof end === 'number' ? end : null
+    }).catch((err) => console.error('Share upload failed:', err));
+};
+
+export async function shareDownload(record, serverUrl) {
+    await initListeners();
+
+    const conn = await connect(serverUrl);
+    if (!conn || !conn.isConnected) {
+        throw new Error('无法连接到服务器');
+    }
+
+    const offer = await invoke('share_download', { id: record.id, server: serverUrl });
+    sharedNames.set(offer.fileId, offer.fileName);
+    if (!conn.callbacks.onStartUpload.includes(onStartUpload)) {
+        registerCallback(serverUrl, 'onStartUpload', onStartUpload);
+    }
+    emit(serverUrl, 'file-meta', offer);
+
+    return offer;
+}